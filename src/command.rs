@@ -0,0 +1,206 @@
+/// Split a command line into words the way a shell would: whitespace separates words unless it
+/// is quoted or escaped, single quotes are literal, and backslashes escape the next character
+/// (outside of single quotes). Unterminated quotes extend to the end of the line.
+pub fn tokenize(command: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut chars = command.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                in_word = true;
+                for c in chars.by_ref() {
+                    if c == '\'' {
+                        break;
+                    }
+                    word.push(c);
+                }
+            }
+            '"' => {
+                in_word = true;
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => match chars.next() {
+                            Some(escaped @ ('"' | '\\' | '$' | '`')) => word.push(escaped),
+                            Some(other) => {
+                                word.push('\\');
+                                word.push(other);
+                            }
+                            None => word.push('\\'),
+                        },
+                        _ => word.push(c),
+                    }
+                }
+            }
+            '\\' => {
+                in_word = true;
+                if let Some(escaped) = chars.next() {
+                    word.push(escaped);
+                }
+            }
+            c if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            _ => {
+                in_word = true;
+                word.push(c);
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+
+    words
+}
+
+/// The file name of the executable in a command line (e.g., `nvim` for `/usr/bin/nvim foo.txt`),
+/// or an empty string if there is none. Variables set for the command, with or without `env`
+/// (e.g., `env FOO=1 nvim`), are skipped.
+pub fn executable(command: &str) -> String {
+    tokenize(command)
+        .iter()
+        .filter(|word| !is_assignment(word))
+        .map(|word| word.rsplit('/').next().unwrap_or(""))
+        .find(|program| *program != "env")
+        .unwrap_or("")
+        .to_string()
+}
+
+/// Whether a word sets a variable, e.g., `FOO=1`.
+fn is_assignment(word: &str) -> bool {
+    word.split_once('=').is_some_and(|(name, _)| {
+        !name.is_empty()
+            && !name.starts_with(|c: char| c.is_ascii_digit())
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    })
+}
+
+/// Split a command line into the commands of its `&&`, `||`, `;`, `&`, and `|` chains, leaving
+/// quoted and escaped separators alone. Any of these may be the one in the foreground.
+pub fn segments(command: &str) -> Vec<String> {
+    let mut segments = Vec::new();
+    let mut segment = String::new();
+    let mut quote = None;
+    let mut chars = command.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                segment.push(c);
+            }
+            (Some(q), _) if c == q => {
+                quote = None;
+                segment.push(c);
+            }
+            (Some('"') | None, '\\') => {
+                segment.push(c);
+                if let Some(escaped) = chars.next() {
+                    segment.push(escaped);
+                }
+            }
+            (None, '&')
+                if segment.ends_with('>')
+                    || segment.ends_with('<')
+                    || chars.peek() == Some(&'>') =>
+            {
+                // A redirection such as `2>&1` or `&>log`, not a separator.
+                segment.push(c);
+            }
+            (None, ';' | '&' | '|') => {
+                if chars.peek() == Some(&c) && c != ';' {
+                    chars.next();
+                }
+                segments.push(std::mem::take(&mut segment));
+            }
+            _ => segment.push(c),
+        }
+    }
+    segments.push(segment);
+
+    segments
+        .into_iter()
+        .map(|segment| segment.trim().to_string())
+        .filter(|segment| !segment.is_empty())
+        .collect()
+}
+
+/// The most characters of a command kept by `sanitize`; a heredoc can be any length.
+const MAX_SANITIZED_CHARS: usize = 1000;
+
+/// Flatten a reported command for matching and logging: control characters (including the
+/// newlines of heredocs and wrapped prompts) become spaces, runs of whitespace collapse, and
+/// anything past `MAX_SANITIZED_CHARS` is cut off with a `…`.
+pub fn sanitize(command: &str) -> String {
+    let sanitized = command
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    match sanitized.char_indices().nth(MAX_SANITIZED_CHARS) {
+        Some((end, _)) => format!("{}…", &sanitized[..end]),
+        None => sanitized,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokenizes_like_a_shell() {
+        assert!(tokenize("").is_empty());
+        assert!(tokenize("   ").is_empty());
+        assert_eq!(
+            tokenize(r#"'/opt/my tools/nvim' "notes on \"rust\".md" a\ b"#),
+            ["/opt/my tools/nvim", r#"notes on "rust".md"#, "a b"]
+        );
+        assert_eq!(tokenize("env FOO=1 nvim"), ["env", "FOO=1", "nvim"]);
+    }
+
+    #[test]
+    fn finds_the_executable() {
+        assert_eq!(executable(""), "");
+        assert_eq!(executable("'/opt/my tools/nvim' main.rs"), "nvim");
+        assert_eq!(executable(r#"\"vim\" notes"#), r#""vim""#);
+        assert_eq!(executable("env FOO=1 nvim"), "nvim");
+        assert_eq!(executable("PATH=/opt/bin BAR= /usr/bin/vim"), "vim");
+        assert_eq!(executable("make CC=clang"), "make");
+    }
+
+    #[test]
+    fn splits_command_chains() {
+        assert_eq!(
+            segments("make build && nvim src/main.rs"),
+            ["make build", "nvim src/main.rs"]
+        );
+        assert_eq!(segments("a | b || c"), ["a", "b", "c"]);
+        assert_eq!(segments("cd src; vim;; ls &"), ["cd src", "vim", "ls"]);
+        assert_eq!(segments("make 2>&1 | less"), ["make 2>&1", "less"]);
+        assert_eq!(segments("make &>log"), ["make &>log"]);
+        assert_eq!(
+            segments(r#"echo 'a && b' "c; d" e\|f"#),
+            [r#"echo 'a && b' "c; d" e\|f"#]
+        );
+        assert!(segments("").is_empty());
+    }
+
+    #[test]
+    fn sanitizes_reported_commands() {
+        assert_eq!(sanitize("  vim\tmain.rs\x1b[0m "), "vim main.rs [0m");
+        assert_eq!(sanitize("cat <<EOF\nhello\r\nEOF\n"), "cat <<EOF hello EOF");
+        let long = format!("vim {}", "é".repeat(2 * MAX_SANITIZED_CHARS));
+        let sanitized = sanitize(&long);
+        assert_eq!(sanitized.chars().count(), MAX_SANITIZED_CHARS + 1);
+        assert!(sanitized.starts_with("vim éé") && sanitized.ends_with("é…"));
+    }
+}
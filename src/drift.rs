@@ -0,0 +1,38 @@
+/// How long zellij has to confirm a switch with a ModeUpdate before the switch is presumed lost.
+const CONFIRMATION_SECONDS: f64 = 2.0;
+
+/// Zellij cannot be asked for its mode, only tell us when it changes, so a switch it never
+/// confirms (e.g., lost while reloading, or overridden before it applied) leaves us believing in
+/// a mode it is not in. This keeps the time of the latest switch, to tell when it is overdue.
+#[derive(Default)]
+pub struct Drift {
+    switched_seconds: f64,
+}
+
+impl Drift {
+    /// Note a switch sent at `clock_seconds`, returning how long until it is overdue.
+    pub fn switched(&mut self, clock_seconds: f64) -> f64 {
+        self.switched_seconds = clock_seconds;
+        CONFIRMATION_SECONDS
+    }
+
+    /// How long the unconfirmed switch has left until it is overdue, or `None` if it is.
+    pub fn seconds_left(&self, clock_seconds: f64) -> Option<f64> {
+        let unconfirmed_seconds = clock_seconds - self.switched_seconds;
+        Some(CONFIRMATION_SECONDS - unconfirmed_seconds).filter(|seconds_left| *seconds_left > 0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_overdue_once_the_confirmation_time_is_up() {
+        let mut drift = Drift::default();
+        assert_eq!(drift.switched(10.0), CONFIRMATION_SECONDS);
+        assert_eq!(drift.seconds_left(10.5), Some(CONFIRMATION_SECONDS - 0.5));
+        assert_eq!(drift.seconds_left(10.0 + CONFIRMATION_SECONDS), None);
+        assert_eq!(drift.seconds_left(20.0), None);
+    }
+}
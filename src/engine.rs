@@ -0,0 +1,4803 @@
+//! The decisions of the plugin, kept apart from zellij itself: events go in, and the calls to make
+//! into zellij come out as [`Action`]s, so that sequences of events can be replayed in tests.
+
+use crate::command;
+use crate::drift::Drift;
+use crate::hooks::Hooks;
+use crate::instances::{Instances, News};
+use crate::latency::Latency;
+use crate::log::{self, LogFile, LogFormat, LogLevel};
+use crate::persist::Persisted;
+use crate::trigger::{self, Triggers};
+use crate::webhook::Webhook;
+use crate::zjstatus::Zjstatus;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use zellij_autolock_messages::{
+    Command, State, INSTANCE_MESSAGE_NAME, LOG_MESSAGE_NAME, PANE_ID_ARG, PIPE_NAME,
+};
+use zellij_tile::prelude::{
+    BareKey, ClientId, ClientInfo, Event, EventType, InputMode, PaneId, PaneInfo, PaneManifest,
+    PermissionStatus, PermissionType, PipeMessage, PipeSource,
+};
+// A pure helper, despite living among the host calls.
+use zellij_tile::shim::get_focused_tab;
+
+/// A call into zellij requested by the [`Engine`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum Action {
+    RequestPermissions(Vec<PermissionType>),
+    Subscribe(Vec<EventType>),
+    Unsubscribe(Vec<EventType>),
+    ListClients,
+    SetTimeout(f64),
+    SwitchToInputMode(InputMode),
+    ShowSelf,
+    HideSelf,
+    /// Reply to a `zellij pipe` invocation.
+    PipeOutput {
+        name: String,
+        output: String,
+    },
+    /// Run a command on the host, in the background.
+    RunCommand(Vec<String>),
+    /// POST a JSON body to a URL, without waiting for the response.
+    PostJson {
+        url: String,
+        body: String,
+    },
+    /// Send a message to another plugin, launching it if it isn't running, or to every plugin
+    /// without a URL.
+    PipeToPlugin {
+        plugin_url: Option<String>,
+        name: String,
+        payload: String,
+    },
+}
+
+struct TabPane {
+    tab_id: u32,
+    pane_id: u32,
+    pane_is_plugin: bool,
+    pane_is_fullscreen: bool,
+    pane_title: String,
+}
+
+/// Tabs only have a position, which shifts as tabs are created, closed, or moved, so they are
+/// identified across updates by the panes they contain.
+struct TrackedTab {
+    id: u32,
+    pane_ids: BTreeSet<(u32, bool)>,
+    /// As of the latest `TabUpdate` that agreed with a pane manifest, to tell whether a new one
+    /// still does.
+    name: Option<String>,
+}
+
+struct CommandPane {
+    command: String,
+    is_running: bool,
+    is_editor: bool,
+}
+
+/// Who switched zellij into Locked mode most recently.
+#[derive(Clone, Copy)]
+enum LockOrigin {
+    Autolock,
+    User,
+}
+
+/// Why a mode switch was decided on, for the one line that records it once it is made.
+struct SwitchReason {
+    pane_id: u32,
+    command: String,
+    rule: String,
+    event: String,
+}
+
+/// A switch autolock made, for `undo`.
+struct AutomaticSwitch {
+    from: InputMode,
+    to: InputMode,
+    /// The pane and command that the switch was decided on.
+    assessment: Option<(u32, String)>,
+}
+
+/// Something worth remembering for `dump-trace`. Events are kept by type alone, so tracing stays
+/// cheap when nothing is wrong.
+enum TraceEntry {
+    Event(EventType),
+    Pipe(String),
+    Assessment {
+        pane_id: u32,
+        command: String,
+        is_trigger: bool,
+    },
+    Switch(InputMode),
+    SuppressedSwitch(InputMode),
+}
+
+/// Counts of what the plugin did since it was loaded, to quantify its overhead and any flapping.
+#[derive(Default)]
+struct Metrics {
+    mode_switches: u64,
+    /// Switches decided on but not made: in modes other than Normal and Locked, or undone before
+    /// they were sent.
+    suppressed_switches: u64,
+    list_clients_calls: u64,
+    timer_fires: u64,
+    matched_triggers: u64,
+    /// Time spent in Locked mode, and in any other, up to the latest mode change.
+    locked_seconds: f64,
+    unlocked_seconds: f64,
+    /// How often each trigger made autolock lock zellij.
+    trigger_locks: BTreeMap<String, u64>,
+}
+
+pub struct Engine {
+    is_enabled: bool,
+    permissions_granted: bool,
+    permissions_denied: bool,
+    configuration_errors: Vec<String>,
+    /// Where `Persisted` state is kept, or `None` outside zellij or once writing it failed.
+    persist_file: Option<String>,
+    persisted: Persisted,
+    /// Where a project keeps its own triggers, or `None` outside zellij.
+    lock_trigger_cmds: Vec<String>,
+    triggers: Triggers,
+    /// Triggers whose command lines are never logged, from the `secrets` preset.
+    secret_triggers: Triggers,
+    reaction_seconds: f64,
+    max_poll_seconds: f64,
+    max_polls_per_second: usize,
+    recent_polls: VecDeque<f64>,
+    clock_seconds: f64,
+    poll_seconds: f64,
+    /// When the soonest pending timer fires, on the clock of `clock_seconds`.
+    timer_deadline_seconds: Option<f64>,
+    is_initialized: bool,
+    latest_tab_pane: TabPane,
+    tabs: BTreeMap<usize, TrackedTab>,
+    next_tab_id: u32,
+    /// The focused tab's position and the names of all tabs, from a `TabUpdate` that the pane
+    /// manifest has yet to catch up with.
+    focused_tab_hint: Option<(usize, Vec<String>)>,
+    latest_pane_manifest: PaneManifest,
+    floating_panes_visible: bool,
+    latest_swap_layout: Option<String>,
+    latest_mode: InputMode,
+    /// When `latest_mode` was entered, for the time spent in each mode.
+    latest_mode_seconds: f64,
+    expected_mode: Option<InputMode>,
+    /// Whether zellij is overdue to confirm the switch to `expected_mode`.
+    drift: Drift,
+    pending_mode_switch: Option<(InputMode, Option<InputMode>)>,
+    pending_switch_reason: Option<SwitchReason>,
+    locked_by: Option<LockOrigin>,
+    latest_assessment: Option<(u32, String)>,
+    command_panes: BTreeMap<u32, CommandPane>,
+    detected_commands: BTreeMap<u32, String>,
+    root_trigger_panes: BTreeMap<u32, bool>,
+    replaced_panes: BTreeMap<u32, u32>,
+    titled_panes: BTreeSet<u32>,
+    /// Panes whose editor said it is active, through `editor-enter`; their lock needs no polling.
+    editor_panes: BTreeSet<u32>,
+    /// Panes whose shell reports its commands through `cmd-start` and `cmd-end`, with the command
+    /// running in each, if any.
+    shell_panes: BTreeMap<u32, Option<String>>,
+    /// Until when a `request-unlock` keeps zellij unlocked, and the mode to restore then.
+    unlocked_until: Option<(f64, InputMode)>,
+    /// Decide and log as usual, but never switch modes.
+    dry_run: bool,
+    latest_switch: Option<AutomaticSwitch>,
+    /// The assessment whose switch was undone; it is not acted on again.
+    undone_assessment: Option<(u32, String)>,
+    /// Time switches, from `measure_latency`.
+    latency: Option<Latency>,
+    /// Lock after this many seconds without input, whatever runs, until the next input.
+    idle_lock_seconds: Option<f64>,
+    latest_input_seconds: f64,
+    /// The mode to go back to on the next input, while locked for being idle.
+    idle_locked: Option<InputMode>,
+    /// Plugins (matched against their URLs) that take over the screen; locking underneath one
+    /// would trap the user in it.
+    overlay_plugins: Vec<String>,
+    /// Sessions (by name, `*` and `?` allowed) running inside an IDE's terminal, where autolock
+    /// stays off.
+    embedded_sessions: Triggers,
+    /// With several instances loaded (e.g., each with its own triggers), only one switches modes.
+    coordinate_instances: bool,
+    instance_id: Option<u32>,
+    instances: Instances,
+    latest_session_name: Option<String>,
+    current_client_id: Option<ClientId>,
+    unanswered_list_clients: u32,
+    list_clients_in_flight: Option<u32>,
+    list_clients_deferred: bool,
+    list_clients_retries: u32,
+    is_typing: bool,
+    typing_deferrals: u32,
+    is_visible: bool,
+    is_hiding_self: bool,
+    /// Whether our own pane is hidden, so that not being visible means its client went away.
+    is_self_hidden: bool,
+    log_level: LogLevel,
+    log_format: LogFormat,
+    log_file: Option<RefCell<LogFile>>,
+    log_file_max_bytes: u64,
+    log_file_keep: usize,
+    /// Plugin that log lines are piped to as they are written, e.g., a log viewer.
+    log_to_plugin: Option<String>,
+    forwarded_log_lines: RefCell<Vec<String>>,
+    /// The latest message, and how many times it has been repeated since it was written.
+    repeated_log: RefCell<Option<(String, u32, LogLevel)>>,
+    /// What zjstatus shows, from `zjstatus_format`.
+    zjstatus: Option<Zjstatus>,
+    /// Where to keep the state as JSON for tools outside zellij, and what was written there last.
+    state_file: Option<String>,
+    state_file_contents: Option<String>,
+    latest_trigger: Option<String>,
+    /// When a session switch began, and until when decisions wait for the new session to settle.
+    session_settling: Option<(f64, f64)>,
+    /// For users who keep zellij locked by default: triggers still lock it, but nothing unlocks it.
+    is_inverse: bool,
+    /// The mode zellij starts in (its `default_mode`), once a `ModeUpdate` names it.
+    base_mode: Option<InputMode>,
+    hooks: Hooks,
+    /// From `webhook_url`.
+    webhook: Option<Webhook>,
+    unix_seconds: f64,
+    current_event: Option<EventType>,
+    subscriptions: Vec<EventType>,
+    metrics: Metrics,
+    trace: VecDeque<(f64, TraceEntry)>,
+    /// When each type of event last arrived, for `doctor`.
+    events_seen: HashMap<EventType, f64>,
+    /// Whether the latest `list_clients` answer named the current client's pane, and when.
+    latest_list_clients_answer: Option<(f64, bool)>,
+    actions: Vec<Action>,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self {
+            is_enabled: true,
+            permissions_granted: false,
+            permissions_denied: false,
+            configuration_errors: Vec::new(),
+            persist_file: None,
+            persisted: Persisted::default(),
+            lock_trigger_cmds: DEFAULT_TRIGGERS.map(str::to_string).to_vec(),
+            triggers: Triggers::compile(&DEFAULT_TRIGGERS.map(str::to_string)),
+            secret_triggers: Triggers::default(),
+            reaction_seconds: 0.3,
+            max_poll_seconds: 10.0,
+            max_polls_per_second: 0,
+            recent_polls: VecDeque::new(),
+            clock_seconds: 0.0,
+            poll_seconds: 0.3,
+            timer_deadline_seconds: None,
+            is_initialized: false,
+            latest_tab_pane: TabPane {
+                tab_id: u32::MAX,
+                pane_id: u32::MAX,
+                pane_is_plugin: false,
+                pane_is_fullscreen: false,
+                pane_title: "".to_string(),
+            },
+            tabs: BTreeMap::new(),
+            next_tab_id: 0,
+            focused_tab_hint: None,
+            latest_pane_manifest: PaneManifest::default(),
+            floating_panes_visible: false,
+            latest_swap_layout: None,
+            latest_mode: InputMode::Normal,
+            latest_mode_seconds: 0.0,
+            expected_mode: None,
+            drift: Drift::default(),
+            pending_mode_switch: None,
+            pending_switch_reason: None,
+            locked_by: None,
+            latest_assessment: None,
+            command_panes: BTreeMap::new(),
+            detected_commands: BTreeMap::new(),
+            root_trigger_panes: BTreeMap::new(),
+            replaced_panes: BTreeMap::new(),
+            titled_panes: BTreeSet::new(),
+            editor_panes: BTreeSet::new(),
+            shell_panes: BTreeMap::new(),
+            unlocked_until: None,
+            dry_run: false,
+            latency: None,
+            latest_switch: None,
+            undone_assessment: None,
+            idle_lock_seconds: None,
+            latest_input_seconds: 0.0,
+            idle_locked: None,
+            embedded_sessions: Triggers::default(),
+            coordinate_instances: false,
+            instance_id: None,
+            instances: Instances::default(),
+            overlay_plugins: vec!["session-manager".to_string(), "plugin-manager".to_string()],
+            latest_session_name: None,
+            current_client_id: None,
+            unanswered_list_clients: 0,
+            list_clients_in_flight: None,
+            list_clients_deferred: false,
+            list_clients_retries: 0,
+            is_typing: false,
+            typing_deferrals: 0,
+            is_visible: true,
+            is_hiding_self: false,
+            is_self_hidden: false,
+            log_level: LogLevel::Off,
+            log_format: LogFormat::Text,
+            log_file: None,
+            log_file_max_bytes: 1024 * 1024,
+            log_file_keep: 3,
+            log_to_plugin: None,
+            forwarded_log_lines: RefCell::new(Vec::new()),
+            repeated_log: RefCell::new(None),
+            zjstatus: None,
+            state_file: None,
+            state_file_contents: None,
+            latest_trigger: None,
+            session_settling: None,
+            is_inverse: false,
+            base_mode: None,
+            hooks: Hooks::default(),
+            webhook: None,
+            unix_seconds: 0.0,
+            current_event: None,
+            subscriptions: Vec::new(),
+            metrics: Metrics::default(),
+            trace: VecDeque::with_capacity(TRACE_CAPACITY),
+            events_seen: HashMap::new(),
+            latest_list_clients_answer: None,
+            actions: Vec::new(),
+        }
+    }
+}
+
+/// The triggers unless `triggers` says otherwise.
+const DEFAULT_TRIGGERS: [&str; 6] = ["nvim", "vim", "git", "fzf", "zoxide", "atuin"];
+
+/// The configuration used when the plugin is loaded with none at all (e.g., as a bare `autolock`
+/// alias): the default triggers, plus the `secrets` preset.
+const BARE_CONFIGURATION: [(&str, &str); 1] = [("presets", "secrets")];
+
+/// After this many `list_clients` requests without a response, assume our subscriptions or
+/// permissions were lost.
+const MAX_UNANSWERED_LIST_CLIENTS: u32 = 3;
+
+/// How often to retry when `list_clients` has no usable current client.
+const MAX_LIST_CLIENTS_RETRIES: u32 = 5;
+
+/// Messages vim-zellij-navigator handles before moving focus or resizing, for when they reach us
+/// too (e.g., `zellij pipe` without `--plugin` goes to every plugin).
+const NAVIGATOR_MESSAGES: [&str; 3] = ["move_focus", "move_focus_or_tab", "resize"];
+
+/// How long tabs and panes must go unchanged after a session switch before decisions resume.
+const SESSION_SETTLE_SECONDS: f64 = 0.5;
+/// The longest decisions wait for a new session to settle, however busy it keeps.
+const MAX_SESSION_SETTLE_SECONDS: f64 = 3.0;
+
+/// Events that reach any working session; `doctor` fails when these are missing. Others only
+/// follow from what the user does (e.g., clicks or command panes).
+const ESSENTIAL_EVENT_TYPES: [EventType; 4] = [
+    EventType::ListClients,
+    EventType::ModeUpdate,
+    EventType::PaneUpdate,
+    EventType::TabUpdate,
+];
+
+/// Events that can lead to a switch, from which `measure_latency` times it.
+const LATENCY_START_EVENT_TYPES: [EventType; 7] = [
+    EventType::CommandPaneExited,
+    EventType::CommandPaneOpened,
+    EventType::EditPaneExited,
+    EventType::EditPaneOpened,
+    EventType::InputReceived,
+    EventType::PaneUpdate,
+    EventType::TabUpdate,
+];
+
+/// Shells run in nearly every pane, so triggering on one locks zellij for good.
+const SHELLS: [&str; 6] = ["sh", "bash", "zsh", "fish", "nu", "pwsh"];
+
+/// How many events and decisions `dump-trace` looks back on.
+const TRACE_CAPACITY: usize = 200;
+
+/// How many ticks to wait for a pause in typing before assessing anyway, lest keys meant for a
+/// trigger that just started go to zellij instead.
+const MAX_TYPING_DEFERRALS: u32 = 3;
+
+fn get_terminal_pane(pane_id: u32, pane_manifest: &PaneManifest) -> Option<PaneInfo> {
+    pane_manifest
+        .panes
+        .values()
+        .flatten()
+        .find(|pane| !pane.is_plugin && pane.id == pane_id)
+        .cloned()
+}
+
+/// The arrangement of the panes in a tab, for noticing when a layout change moves them around.
+fn get_tab_layout(
+    tab_position: usize,
+    pane_manifest: &PaneManifest,
+) -> Vec<(u32, bool, usize, usize, usize, usize)> {
+    pane_manifest
+        .panes
+        .get(&tab_position)
+        .map(|panes| {
+            panes
+                .iter()
+                .map(|pane| {
+                    (
+                        pane.id,
+                        pane.is_plugin,
+                        pane.pane_x,
+                        pane.pane_y,
+                        pane.pane_rows,
+                        pane.pane_columns,
+                    )
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Relative paths are relative to the folder zellij was started in, which plugins see as `/host`.
+fn host_path(path: &str) -> String {
+    if path.starts_with('/') {
+        path.to_string()
+    } else {
+        format!("/host/{}", path)
+    }
+}
+
+/// Like `get_focused_pane`, but includes plugin panes and prefers a fullscreen pane or the
+/// floating layer when it is visible, since that is where input actually goes.
+fn get_focused_pane_or_plugin(
+    tab_position: usize,
+    floating_panes_visible: bool,
+    pane_manifest: &PaneManifest,
+) -> Option<PaneInfo> {
+    let panes = pane_manifest.panes.get(&tab_position)?;
+    if let Some(pane) = panes
+        .iter()
+        .find(|pane| pane.is_fullscreen && pane.is_selectable && !pane.is_suppressed)
+    {
+        return Some(pane.clone());
+    }
+    let mut focused_panes = panes
+        .iter()
+        .filter(|pane| pane.is_focused && pane.is_selectable && !pane.is_suppressed);
+    if floating_panes_visible {
+        if let Some(pane) = focused_panes.clone().find(|pane| pane.is_floating) {
+            return Some(pane.clone());
+        }
+    }
+    focused_panes.find(|pane| !pane.is_floating).cloned()
+}
+
+impl Engine {
+    pub fn load(&mut self, configuration: BTreeMap<String, String>) {
+        let is_bare = configuration.is_empty();
+        let configuration = if is_bare {
+            BARE_CONFIGURATION
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect()
+        } else {
+            configuration
+        };
+        // Which events we need depends on the configuration.
+        self.configuration_errors.clear();
+        self.load_configuration(configuration);
+        self.restore_persisted();
+        for error in self.configuration_errors.clone() {
+            log!(self, Error, "Configuration: {}", error);
+        }
+        if !self.configuration_errors.is_empty() && cfg!(feature = "ui") {
+            self.show_self();
+        }
+        if is_bare {
+            log!(self, Info, "No configuration; using the defaults.");
+        }
+        self.subscribe();
+        if self.permissions_granted {
+            self.hide_self();
+        }
+    }
+
+    /// Returns whether the plugin should render.
+    pub fn update(&mut self, event: Event) -> bool {
+        self.current_event = Some(EventType::from(&event));
+        self.trace(TraceEntry::Event(EventType::from(&event)));
+        self.events_seen
+            .insert(EventType::from(&event), self.clock_seconds);
+        if let Some(latency) = self.latency.as_mut() {
+            let event_type = EventType::from(&event);
+            if LATENCY_START_EVENT_TYPES.contains(&event_type) {
+                latency.start(format!("{:?}", event_type), self.clock_seconds);
+            } else if event_type == EventType::Timer {
+                latency.timer_fired(self.clock_seconds);
+            }
+        }
+        match event {
+            Event::PermissionRequestResult(permission) => {
+                self.permissions_granted = match permission {
+                    PermissionStatus::Granted => true,
+                    PermissionStatus::Denied => false,
+                };
+                self.permissions_denied = !self.permissions_granted;
+                self.update_subscriptions();
+                if self.permissions_granted {
+                    // Anything sent to zjstatus before the grant was dropped, so send it again.
+                    if let Some(zjstatus) = self.zjstatus.as_mut() {
+                        zjstatus.resend();
+                    }
+                    self.hide_self();
+                    // Ask for the current client's pane ourselves rather than relying on which
+                    // of TabUpdate and PaneUpdate happens to arrive first.
+                    self.list_clients();
+                } else if cfg!(feature = "ui") {
+                    log!(self, Warn, "Permissions denied.");
+                    // Without permissions we can do nothing, so at least say so.
+                    self.show_self();
+                    return true;
+                }
+            }
+
+            Event::Key(key) => {
+                if self.permissions_denied && key.is_key_without_modifier(BareKey::Char('r')) {
+                    self.subscribe();
+                }
+            }
+
+            Event::Visible(is_visible) => {
+                if !is_visible && self.is_hiding_self {
+                    // Hiding our own pane is not a reason to pause.
+                    self.is_hiding_self = false;
+                    self.is_self_hidden = true;
+                } else if is_visible != self.is_visible && (is_visible || self.is_self_hidden) {
+                    // Nor is a tab switch away from our pane while it is shown, e.g., with
+                    // configuration errors.
+                    self.is_visible = is_visible;
+                    log!(
+                        self,
+                        Info,
+                        "{}",
+                        if is_visible { "Resumed" } else { "Paused" }
+                    );
+                    if is_visible {
+                        self.assess_focused_pane();
+                        self.start_timer();
+                    }
+                }
+            }
+
+            Event::ModeUpdate(mode_info) => {
+                let mode_seconds = self.clock_seconds - self.latest_mode_seconds;
+                if self.latest_mode == InputMode::Locked {
+                    self.metrics.locked_seconds += mode_seconds;
+                } else {
+                    self.metrics.unlocked_seconds += mode_seconds;
+                }
+                self.latest_mode_seconds = self.clock_seconds;
+                let is_own_switch = self.expected_mode.take() == Some(mode_info.mode);
+                if mode_info.mode == InputMode::Locked && self.latest_mode != InputMode::Locked {
+                    self.locked_by = Some(if is_own_switch {
+                        LockOrigin::Autolock
+                    } else {
+                        LockOrigin::User
+                    });
+                }
+                self.latest_mode = mode_info.mode;
+                if mode_info.base_mode.is_some() {
+                    self.base_mode = mode_info.base_mode;
+                }
+                if is_own_switch {
+                    // This is the echo of our own switch, not an external change.
+                    return false;
+                }
+                // Decide again, even if the command did not change.
+                self.latest_assessment = None;
+                // The user took over from a `request-unlock`; there is nothing left to restore.
+                self.unlocked_until = None;
+                self.start_timer();
+            }
+
+            Event::InputReceived => {
+                self.latest_input_seconds = self.clock_seconds;
+                if let Some(restore_mode) = self.idle_locked.take() {
+                    log!(
+                        self,
+                        Info,
+                        "Input after being idle; going back to {:?}.",
+                        restore_mode
+                    );
+                    self.latest_assessment = None;
+                    if restore_mode != InputMode::Locked {
+                        self.pending_switch_reason =
+                            Some(self.focused_pane_switch_reason("idle lock ended"));
+                        self.switch_to_input_mode(restore_mode);
+                    }
+                }
+                self.watch_idle();
+                self.list_clients_retries = 0;
+                // Input while a tick is pending means the user is still typing.
+                self.is_typing |= self.timer_deadline_seconds.is_some();
+                self.start_polling();
+            }
+
+            Event::TabUpdate(tab_info) => {
+                self.extend_session_settling();
+                if let Some(tab) = get_focused_tab(&tab_info) {
+                    // Positions shift when tabs are closed or moved, so they are only trusted
+                    // while every tab is where the pane manifest last put it; otherwise the next
+                    // pane manifest settles which tab is focused.
+                    let positions_current = self.tabs.len() == tab_info.len()
+                        && tab_info.iter().all(|tab| {
+                            self.tabs.get(&tab.position).is_some_and(|tracked_tab| {
+                                tracked_tab
+                                    .name
+                                    .as_ref()
+                                    .is_none_or(|name| *name == tab.name)
+                            })
+                        });
+                    let pane_in_tab = positions_current
+                        && self
+                            .latest_pane_manifest
+                            .panes
+                            .get(&tab.position)
+                            .is_some_and(|panes| {
+                                panes.iter().any(|pane| self.is_focused_pane(pane))
+                            });
+                    let tab_names = tab_info.iter().map(|tab| tab.name.clone()).collect();
+                    if positions_current {
+                        self.latest_tab_pane.tab_id = self.tab_id_at(tab.position);
+                        self.name_tabs(tab_names);
+                        self.focused_tab_hint = None;
+                    } else {
+                        self.focused_tab_hint = Some((tab.position, tab_names));
+                    }
+                    self.floating_panes_visible = tab.are_floating_panes_visible;
+                    let swap_layout_changed =
+                        tab.active_swap_layout_name != self.latest_swap_layout;
+                    self.latest_swap_layout = tab.active_swap_layout_name.clone();
+                    if swap_layout_changed && pane_in_tab {
+                        log!(
+                            self,
+                            Debug,
+                            "Swap layout changed: {:?}",
+                            self.latest_swap_layout
+                        );
+                        self.assess_focused_pane();
+                    }
+                    if !pane_in_tab && positions_current {
+                        self.latest_tab_pane.pane_id = u32::MAX;
+                        if let Some(pane) = get_focused_pane_or_plugin(
+                            tab.position,
+                            self.floating_panes_visible,
+                            &self.latest_pane_manifest,
+                        ) {
+                            self.set_focused_pane(pane);
+                            self.assess_focused_pane();
+                        }
+                    }
+                }
+                if self.try_initialize() {
+                    self.assess_focused_pane();
+                }
+            }
+
+            Event::PaneUpdate(pane_manifest) => {
+                self.extend_session_settling();
+                let previous_tab_position = self.focused_tab_position();
+                self.track_tabs(&pane_manifest);
+                let mut tab_from_hint = false;
+                if let Some((position, tab_names)) = self.focused_tab_hint.take() {
+                    if pane_manifest.panes.len() == tab_names.len() {
+                        self.latest_tab_pane.tab_id = self.tab_id_at(position);
+                        self.name_tabs(tab_names);
+                        tab_from_hint = true;
+                    } else {
+                        self.focused_tab_hint = Some((position, tab_names));
+                    }
+                }
+                if !tab_from_hint {
+                    self.follow_moved_pane(previous_tab_position, &pane_manifest);
+                }
+                if self.focused_tab_position() == usize::MAX {
+                    // No TabUpdate yet, so work out the focused tab from the manifest itself.
+                    if let Some(position) = self.infer_focused_tab(&pane_manifest) {
+                        self.latest_tab_pane.tab_id = self.tab_id_at(position);
+                    }
+                }
+                let tab_position = self.focused_tab_position();
+
+                let focused_pane = get_focused_pane_or_plugin(
+                    tab_position,
+                    self.floating_panes_visible,
+                    &pane_manifest,
+                );
+                let layout_changed = get_tab_layout(tab_position, &pane_manifest)
+                    != get_tab_layout(previous_tab_position, &self.latest_pane_manifest);
+
+                // Keep the commands of known command panes current, and forget those that closed.
+                let pane_commands: BTreeMap<u32, Option<String>> = pane_manifest
+                    .panes
+                    .values()
+                    .flatten()
+                    .filter(|pane| !pane.is_plugin)
+                    .map(|pane| (pane.id, pane.terminal_command.clone()))
+                    .collect();
+                self.command_panes
+                    .retain(|pane_id, _| pane_commands.contains_key(pane_id));
+                self.root_trigger_panes
+                    .retain(|pane_id, _| pane_commands.contains_key(pane_id));
+                self.titled_panes
+                    .retain(|pane_id| pane_commands.contains_key(pane_id));
+                self.replaced_panes.retain(|pane_id, replaced_pane_id| {
+                    pane_commands.contains_key(pane_id)
+                        && pane_commands.contains_key(replaced_pane_id)
+                });
+                if !self.latest_tab_pane.pane_is_plugin
+                    && self.latest_tab_pane.pane_id != u32::MAX
+                    && !pane_commands.contains_key(&self.latest_tab_pane.pane_id)
+                {
+                    // The focused pane is gone; should its id be handed out again, that pane must
+                    // be treated as new rather than as the one we last assessed.
+                    self.latest_tab_pane.pane_id = u32::MAX;
+                    self.latest_assessment = None;
+                }
+
+                let mut command_changed = false;
+                for (pane_id, command_pane) in self.command_panes.iter_mut() {
+                    if let Some(command) = &pane_commands[pane_id] {
+                        if command_pane.command != *command {
+                            command_pane.command = command.clone();
+                            command_changed |= !self.latest_tab_pane.pane_is_plugin
+                                && *pane_id == self.latest_tab_pane.pane_id;
+                        }
+                    }
+                }
+
+                if let Some(pane) = focused_pane {
+                    if !self.is_focused_pane(&pane) {
+                        let previous_pane_id = self.latest_tab_pane.pane_id;
+                        let previous_pane_suppressed = !self.latest_tab_pane.pane_is_plugin
+                            && get_terminal_pane(previous_pane_id, &pane_manifest)
+                                .is_some_and(|previous_pane| previous_pane.is_suppressed);
+                        if !pane.is_plugin && previous_pane_suppressed {
+                            // Opened in place of the previous pane, which returns when it closes.
+                            self.replaced_panes.insert(pane.id, previous_pane_id);
+                        }
+                        self.set_focused_pane(pane);
+
+                        self.assess_focused_pane();
+                    } else if command_changed {
+                        self.latest_tab_pane.pane_title = pane.title;
+                        self.assess_command_pane(pane.id);
+                    } else if pane.title != self.latest_tab_pane.pane_title {
+                        // Renames (by the user or by the shell) often mean the command changed.
+                        log!(
+                            self,
+                            Debug,
+                            "Pane renamed: `{}`",
+                            self.redacted(&command::sanitize(&pane.title))
+                        );
+                        self.latest_tab_pane.pane_title = pane.title;
+                        self.assess_focused_pane();
+                        // Shells tend to rename the pane just before the command starts.
+                        self.start_timer();
+                    } else if pane.is_fullscreen != self.latest_tab_pane.pane_is_fullscreen {
+                        log!(self, Debug, "Fullscreen: {}", pane.is_fullscreen);
+                        self.latest_tab_pane.pane_is_fullscreen = pane.is_fullscreen;
+                        self.assess_focused_pane();
+                    } else if layout_changed {
+                        // Swap layouts rearrange panes without any input we would notice.
+                        self.assess_focused_pane();
+                    }
+                }
+
+                // Detection results for background tabs are kept as a cache, until their panes
+                // close.
+                self.detected_commands
+                    .retain(|pane_id, _| pane_commands.contains_key(pane_id));
+
+                self.latest_pane_manifest = pane_manifest;
+                if self.try_initialize() {
+                    self.assess_focused_pane();
+                }
+            }
+
+            Event::CommandPaneOpened(pane_id, _context)
+            | Event::CommandPaneReRun(pane_id, _context) => {
+                let command = self
+                    .command_panes
+                    .get(&pane_id)
+                    .map(|command_pane| command_pane.command.clone())
+                    .unwrap_or_default();
+                self.command_panes.insert(
+                    pane_id,
+                    CommandPane {
+                        command,
+                        is_running: true,
+                        is_editor: false,
+                    },
+                );
+                if self.is_focused_terminal_pane(pane_id) {
+                    self.assess_command_pane(pane_id);
+                }
+            }
+
+            Event::EditPaneOpened(pane_id, _context) => {
+                // Panes opened by `zellij edit` run the user's editor by construction.
+                let command = self.manifest_command(pane_id).unwrap_or_default();
+                self.command_panes.insert(
+                    pane_id,
+                    CommandPane {
+                        command,
+                        is_running: true,
+                        is_editor: true,
+                    },
+                );
+                if self.is_focused_terminal_pane(pane_id) {
+                    self.assess_command_pane(pane_id);
+                }
+            }
+
+            Event::CommandPaneExited(pane_id, _exit_code, _context)
+            | Event::EditPaneExited(pane_id, _exit_code, _context) => {
+                if let Some(command_pane) = self.command_panes.get_mut(&pane_id) {
+                    command_pane.is_running = false;
+                }
+                if self.is_focused_terminal_pane(pane_id) {
+                    self.assess_command_pane(pane_id);
+                }
+            }
+
+            Event::SessionUpdate(sessions, _resurrectable_sessions) => {
+                if let Some(session) = sessions.iter().find(|session| session.is_current_session) {
+                    if self.latest_session_name.as_ref() != Some(&session.name) {
+                        // When the previous name is gone altogether, the session was renamed
+                        // rather than switched away from, and everything we know still holds.
+                        let is_rename = self.latest_session_name.as_ref().is_some_and(|name| {
+                            !sessions.iter().any(|session| session.name == *name)
+                        });
+                        let is_switch = self.latest_session_name.is_some() && !is_rename;
+                        if is_rename {
+                            log!(
+                                self,
+                                Info,
+                                "Session renamed from `{}` to `{}`.",
+                                self.latest_session_name.as_deref().unwrap_or_default(),
+                                session.name
+                            );
+                        }
+                        self.latest_session_name = Some(session.name.clone());
+                        if self
+                            .embedded_sessions
+                            .matching(&session.name, &session.name)
+                            .is_some()
+                        {
+                            self.turn_off_when_embedded("its name matches `embedded_sessions`");
+                            self.update_subscriptions();
+                        }
+                        if is_switch {
+                            log!(self, Info, "Switched to session `{}`.", session.name);
+                            self.start_session_settling();
+                            self.reset_session_state();
+                            self.track_tabs(&session.panes);
+                            if let Some(tab) = get_focused_tab(&session.tabs) {
+                                self.latest_tab_pane.tab_id = self.tab_id_at(tab.position);
+                                self.floating_panes_visible = tab.are_floating_panes_visible;
+                                if let Some(pane) = get_focused_pane_or_plugin(
+                                    tab.position,
+                                    self.floating_panes_visible,
+                                    &session.panes,
+                                ) {
+                                    self.set_focused_pane(pane);
+                                }
+                            }
+                            self.try_initialize();
+                            self.assess_focused_pane();
+                        }
+                    }
+                }
+            }
+
+            Event::ListClients(clients) => {
+                self.unanswered_list_clients = 0;
+                self.list_clients_in_flight = None;
+                if let Some(current_client) = clients.iter().find(|client| {
+                    client.is_current_client && self.current_client_id != Some(client.client_id)
+                }) {
+                    // A different current client means we were detached and reattached;
+                    // nothing remembered from before still describes what it sees.
+                    self.attach_client(current_client);
+                }
+
+                for client in clients.iter() {
+                    if let PaneId::Terminal(pane_id) = client.pane_id {
+                        let running_command = command::sanitize(&client.running_command);
+                        if !running_command.is_empty() {
+                            self.observe_pane_command(pane_id, &running_command);
+                        }
+                        if !running_command.is_empty() && running_command != "N/A" {
+                            self.observe_pane_title(pane_id, &running_command);
+                            self.detected_commands.insert(pane_id, running_command);
+                        }
+                    }
+                }
+
+                if let Some(current_client) = clients
+                    .iter()
+                    .find(|client| client.is_current_client && !client.running_command.is_empty())
+                {
+                    self.latest_list_clients_answer = Some((self.clock_seconds, true));
+                    self.list_clients_retries = 0;
+                    self.try_initialize();
+                    if !self.is_initialized {
+                        return false;
+                    }
+                    let pane_id = match current_client.pane_id {
+                        PaneId::Terminal(pane_id) => pane_id,
+                        // Locking underneath another plugin would trap the user inside it.
+                        PaneId::Plugin(_) => return false,
+                    };
+                    if self.is_session_settling() || self.is_unlock_requested() {
+                        return false;
+                    }
+                    if self.is_in_background_tab(pane_id) {
+                        // Already cached above; only the focused tab may switch modes.
+                        log!(self, Debug, "Pane {} is in a background tab.", pane_id);
+                        return false;
+                    }
+                    let mut running_command = command::sanitize(&current_client.running_command);
+                    if running_command == "N/A" {
+                        if let Some(fallback_command) = self.manifest_command(pane_id) {
+                            log!(
+                                self,
+                                Debug,
+                                "Falling back to pane manifest: `{}`",
+                                self.redacted(&fallback_command)
+                            );
+                            self.detected_commands
+                                .insert(pane_id, fallback_command.clone());
+                            running_command = fallback_command;
+                        }
+                    }
+                    if self.editor_panes.contains(&pane_id)
+                        || self.shell_panes.contains_key(&pane_id)
+                    {
+                        // The editor or shell tells us what runs there; polls lag behind it.
+                        return false;
+                    }
+                    if self.latest_assessment.as_ref() == Some(&(pane_id, running_command.clone()))
+                    {
+                        // Most polls find the same command as the last; nothing to decide anew.
+                        self.back_off_polling();
+                        return false;
+                    }
+                    self.assess_running_command(pane_id, running_command);
+                } else {
+                    self.latest_list_clients_answer = Some((self.clock_seconds, false));
+                    // Common right after attaching or while reconnecting.
+                    self.retry_list_clients();
+                }
+            }
+
+            Event::PaneClosed(PaneId::Terminal(pane_id)) => {
+                let was_root_trigger = self.root_trigger_panes.remove(&pane_id) == Some(true);
+                self.command_panes.remove(&pane_id);
+                self.detected_commands.remove(&pane_id);
+                self.titled_panes.remove(&pane_id);
+                self.editor_panes.remove(&pane_id);
+                self.shell_panes.remove(&pane_id);
+                if self.is_focused_terminal_pane(pane_id) {
+                    // The pane's process is gone, so its command no longer applies; re-assess
+                    // whichever pane receives focus instead of waiting to poll.
+                    if was_root_trigger {
+                        log!(self, Info, "Trigger pane {} closed.", pane_id);
+                    }
+                    self.latest_assessment = None;
+                    let replaced_pane =
+                        self.replaced_panes
+                            .get(&pane_id)
+                            .and_then(|replaced_pane_id| {
+                                get_terminal_pane(*replaced_pane_id, &self.latest_pane_manifest)
+                            });
+                    if let Some(replaced_pane) = replaced_pane {
+                        // Closing an in-place pane restores the pane it replaced.
+                        self.set_focused_pane(replaced_pane);
+                        self.assess_focused_pane();
+                    } else {
+                        self.latest_tab_pane.pane_id = u32::MAX;
+                        self.list_clients();
+                    }
+                }
+                self.replaced_panes.remove(&pane_id);
+                self.replaced_panes
+                    .retain(|_, replaced_pane_id| *replaced_pane_id != pane_id);
+            }
+
+            Event::Timer(_t) => {
+                self.timer_deadline_seconds = None;
+                self.metrics.timer_fires += 1;
+                if !self.is_enabled {
+                    // Scheduled before we were disabled; timers cannot be cancelled.
+                    return false;
+                }
+                self.watch_idle();
+                self.check_drift();
+                if self.permissions_granted
+                    && self.unanswered_list_clients >= MAX_UNANSWERED_LIST_CLIENTS
+                {
+                    log!(
+                        self,
+                        Warn,
+                        "{} requests went unanswered; subscribing again.",
+                        self.unanswered_list_clients
+                    );
+                    self.subscribe();
+                }
+                // The number of ticks the outstanding request has been waiting for an answer.
+                if let Some(ticks) = self.list_clients_in_flight.as_mut() {
+                    *ticks += 1;
+                }
+                if self.list_clients_in_flight.is_some_and(|ticks| ticks > 1) {
+                    // Still unanswered after a whole tick; presume it lost.
+                    self.list_clients_in_flight = None;
+                }
+                if std::mem::take(&mut self.is_typing)
+                    && self.typing_deferrals < MAX_TYPING_DEFERRALS
+                {
+                    // Assess once per pause in typing rather than once per tick.
+                    self.typing_deferrals += 1;
+                    self.start_timer();
+                    return false;
+                }
+                self.typing_deferrals = 0;
+                let is_deferred = std::mem::take(&mut self.list_clients_deferred);
+                self.assess_focused_pane();
+                if is_deferred && self.list_clients_in_flight.is_none() {
+                    // Whatever asked for the deferred request may not be the focused pane.
+                    self.list_clients();
+                }
+            }
+
+            _ => {}
+        }
+        false // No need to render UI.
+    }
+
+    pub fn pipe(&mut self, pipe_message: PipeMessage) -> bool {
+        self.current_event = None;
+        let mut should_render = false;
+        if pipe_message.name == INSTANCE_MESSAGE_NAME {
+            let payload = pipe_message.payload.as_deref().unwrap_or_default();
+            match self
+                .instances
+                .hear(payload, self.instance_id, self.clock_seconds)
+            {
+                Some(News::Found(peer_id)) => {
+                    log!(self, Info, "Found instance {}.", peer_id);
+                    self.compile_triggers();
+                }
+                Some(News::ChangedTriggers(peer_id)) => {
+                    log!(self, Info, "Instance {} changed its triggers.", peer_id);
+                    self.compile_triggers();
+                }
+                None => {}
+            }
+            return false;
+        }
+        if NAVIGATOR_MESSAGES.contains(&pipe_message.name.as_str()) {
+            // Nothing has moved yet, and the direction is no command of ours; decide once the
+            // PaneUpdate for the move has arrived, rather than against the pane being left.
+            self.trace(TraceEntry::Pipe(pipe_message.name.clone()));
+            log!(
+                self,
+                Debug,
+                "Navigator message `{}`; deciding once focus has moved.",
+                pipe_message.name
+            );
+            if self.is_enabled {
+                self.start_timer();
+            }
+            return false;
+        }
+        let payload = pipe_message.payload.clone().or_else(|| {
+            pipe_message
+                .name
+                .strip_prefix(PIPE_NAME)
+                .and_then(|command| command.strip_prefix(':'))
+                .map(str::to_string)
+        });
+        if let Some(payload) = &payload {
+            let action = payload.trim().to_string();
+            let traced_action = match action.strip_prefix("cmd-start ") {
+                Some(running_command) => format!("cmd-start {}", self.redacted(running_command)),
+                None => action.clone(),
+            };
+            if let Some(latency) = self.latency.as_mut() {
+                let name = traced_action.split_whitespace().next().unwrap_or_default();
+                latency.start(format!("pipe `{}`", name), self.clock_seconds);
+            }
+            self.trace(TraceEntry::Pipe(traced_action));
+
+            match Command::parse(&action) {
+                Some(Command::Enable) => {
+                    self.is_enabled = true;
+                    log!(self, Info, "Enabled");
+                    self.persisted.is_enabled = Some(self.is_enabled);
+                    self.persist();
+                }
+                Some(Command::Disable) => {
+                    self.is_enabled = false;
+                    log!(self, Info, "Disabled");
+                    self.persisted.is_enabled = Some(self.is_enabled);
+                    self.persist();
+                }
+                Some(Command::Toggle) => {
+                    self.is_enabled = !self.is_enabled;
+                    log!(self, Info, "Enabled: {}", self.is_enabled);
+                    self.persisted.is_enabled = Some(self.is_enabled);
+                    self.persist();
+                }
+                Some(Command::RequestPermissions) => self.subscribe(),
+                Some(Command::Status) => {
+                    let output = self.format_status();
+                    log!(self, Info, "Status:\n{}", output);
+                    self.pipe_output(&pipe_message, &output);
+                }
+                Some(Command::Panes) => {
+                    let output = self.format_detected_commands();
+                    log!(self, Info, "Detected commands:\n{}", output);
+                    self.pipe_output(&pipe_message, &output);
+                }
+                Some(Command::DumpTrace) => {
+                    let output = self.format_trace();
+                    log!(self, Info, "Trace:\n{}", output);
+                    self.pipe_output(&pipe_message, &output);
+                }
+                Some(Command::Stats) => {
+                    let output = self.format_stats();
+                    log!(self, Info, "Stats:\n{}", output);
+                    self.pipe_output(&pipe_message, &output);
+                    should_render = true;
+                }
+                Some(Command::Latency) => {
+                    let output = match &self.latency {
+                        Some(latency) => latency.format(),
+                        None => "Set `measure_latency` to measure switches.".to_string(),
+                    };
+                    log!(self, Info, "Latency:\n{}", output);
+                    self.pipe_output(&pipe_message, &output);
+                }
+                Some(Command::Doctor) => {
+                    let output = self.format_doctor();
+                    log!(self, Info, "Doctor:\n{}", output);
+                    self.pipe_output(&pipe_message, &output);
+                }
+                Some(command @ (Command::EditorEnter | Command::EditorLeave)) => {
+                    let pane_id = self.sender_pane_id(&pipe_message);
+                    self.editor_handshake(pane_id, command == Command::EditorEnter);
+                }
+                Some(Command::CmdStart(running_command)) => {
+                    let pane_id = self.sender_pane_id(&pipe_message);
+                    self.shell_reported(pane_id, Some(command::sanitize(&running_command)));
+                }
+                Some(Command::CmdEnd) => {
+                    let pane_id = self.sender_pane_id(&pipe_message);
+                    self.shell_reported(pane_id, None);
+                }
+                Some(Command::RequestUnlock(seconds)) => self.request_unlock(seconds),
+                Some(Command::Undo) => self.undo(),
+                Some(Command::AddTrigger(trigger)) => self.change_trigger(trigger, true),
+                Some(Command::RemoveTrigger(trigger)) => self.change_trigger(trigger, false),
+                Some(Command::Embedded) => self.turn_off_when_embedded("the shell said so"),
+                None => {}
+            }
+        }
+
+        self.update_subscriptions();
+        if self.is_enabled {
+            self.assess_focused_pane();
+            self.start_polling();
+        } else {
+            // Start from scratch once enabled again.
+            self.list_clients_retries = 0;
+            self.latest_assessment = None;
+        }
+
+        should_render // Only to show fresh stats.
+    }
+
+    /// The mode to leave zellij in should the plugin stop: zellij's `default_mode` once known,
+    /// and until then locked for `inverse` setups, which keep zellij locked by default.
+    pub fn safe_mode(&self) -> InputMode {
+        match self.base_mode {
+            Some(mode @ (InputMode::Locked | InputMode::Normal)) => mode,
+            _ if self.is_inverse => InputMode::Locked,
+            _ => InputMode::Normal,
+        }
+    }
+
+    pub fn permissions_denied(&self) -> bool {
+        self.permissions_denied
+    }
+
+    /// Settings that were not understood, and so were left at their defaults.
+    pub fn configuration_errors(&self) -> &[String] {
+        &self.configuration_errors
+    }
+
+    /// The plugin id of this instance, which tells it apart from other instances.
+    pub fn set_instance_id(&mut self, instance_id: u32) {
+        self.instance_id = Some(instance_id);
+    }
+
+    /// Where runtime changes are remembered; without one, they last until the plugin is unloaded.
+    pub fn set_persist_file(&mut self, persist_file: &str) {
+        self.persist_file = Some(persist_file.to_string());
+    }
+
+    /// Seconds elapsed on a monotonic clock, for keeping to the polling budget, and seconds since
+    /// the Unix epoch, for timestamps.
+    pub fn set_clock(&mut self, clock_seconds: f64, unix_seconds: f64) {
+        self.clock_seconds = clock_seconds;
+        self.unix_seconds = unix_seconds;
+    }
+
+    /// The calls into zellij that the events so far have asked for, in order, but with at most
+    /// one mode switch: the last one.
+    pub fn take_actions(&mut self) -> Vec<Action> {
+        if let Some((input_mode, previous_expected_mode)) = self.pending_mode_switch.take() {
+            let previous_mode = previous_expected_mode.unwrap_or(self.latest_mode);
+            if input_mode == previous_mode {
+                // Switched there and back again; zellij need not hear about it.
+                self.expected_mode = previous_expected_mode;
+                self.metrics.suppressed_switches += 1;
+                self.trace(TraceEntry::SuppressedSwitch(input_mode));
+            } else {
+                let is_undo = self
+                    .pending_switch_reason
+                    .as_ref()
+                    .is_some_and(|reason| reason.rule == "undo");
+                if !self.dry_run && !is_undo {
+                    self.latest_switch = Some(AutomaticSwitch {
+                        from: previous_mode,
+                        to: input_mode,
+                        assessment: self.latest_assessment.clone(),
+                    });
+                }
+                if self.dry_run {
+                    // Zellij stays in the mode it is in, so expect no echo of the switch.
+                    self.expected_mode = previous_expected_mode;
+                } else {
+                    self.metrics.mode_switches += 1;
+                    self.trace(TraceEntry::Switch(input_mode));
+                }
+                if let Some(reason) = self.pending_switch_reason.take() {
+                    log!(
+                        self,
+                        Info,
+                        [
+                            pane = reason.pane_id,
+                            command = reason.command,
+                            rule = reason.rule,
+                            from = format!("{:?}", previous_mode).to_lowercase(),
+                            to = format!("{:?}", input_mode).to_lowercase()
+                        ],
+                        "{} from {:?} to {:?} mode: pane {} runs `{}` ({}), seen on {}.",
+                        if self.dry_run {
+                            "Dry run: would have switched"
+                        } else {
+                            "Switched"
+                        },
+                        previous_mode,
+                        input_mode,
+                        reason.pane_id,
+                        reason.command,
+                        reason.rule,
+                        reason.event
+                    );
+                }
+                if let (InputMode::Locked, false, Some(trigger)) =
+                    (input_mode, self.dry_run, &self.latest_trigger)
+                {
+                    *self
+                        .metrics
+                        .trigger_locks
+                        .entry(trigger.clone())
+                        .or_default() += 1;
+                }
+                let clock_seconds = self.clock_seconds;
+                let sample = self
+                    .latency
+                    .as_mut()
+                    .and_then(|latency| latency.switched(clock_seconds));
+                if let Some(sample) = sample {
+                    log!(self, Info, "Switch took {}.", sample);
+                }
+                if !self.dry_run {
+                    // Look again should the switch go unconfirmed.
+                    let confirmation_seconds = self.drift.switched(self.clock_seconds);
+                    self.schedule_timer(confirmation_seconds);
+                    self.actions.push(Action::SwitchToInputMode(input_mode));
+                    self.run_hooks(previous_mode, input_mode);
+                }
+            }
+        }
+        self.pending_switch_reason = None;
+        if let Some(latency) = self.latency.as_mut() {
+            latency.settled();
+        }
+        self.update_zjstatus();
+        self.update_state_file();
+        self.say_hello();
+        let forwarded_log_lines = self.forwarded_log_lines.take();
+        if let (Some(plugin_url), false) = (&self.log_to_plugin, forwarded_log_lines.is_empty()) {
+            self.actions.push(Action::PipeToPlugin {
+                plugin_url: Some(plugin_url.clone()),
+                name: LOG_MESSAGE_NAME.to_string(),
+                payload: forwarded_log_lines.join("\n"),
+            });
+        }
+        std::mem::take(&mut self.actions)
+    }
+
+    /// Polling while nothing changes would repeat the same lines over and over, so a run of
+    /// identical messages is written once, followed by how many times it repeated.
+    fn write_log(&self, level: LogLevel, fields: &[(&str, String)], message: std::fmt::Arguments) {
+        let message = message.to_string();
+        let key = format!("{:?} {:?} {}", level, fields, message);
+        let mut repeated_log = self.repeated_log.borrow_mut();
+        if let Some((latest_key, repeats, _)) = repeated_log.as_mut() {
+            if *latest_key == key {
+                *repeats += 1;
+                return;
+            }
+        }
+        if let Some((_, repeats, latest_level)) = repeated_log.replace((key, 0, level)) {
+            if repeats > 0 {
+                self.write_log_line(
+                    latest_level,
+                    &[],
+                    &format!("(The previous message repeated {} times.)", repeats),
+                );
+            }
+        }
+        drop(repeated_log);
+        self.write_log_line(level, fields, &message);
+    }
+
+    /// Every line says which client, tab, and pane the plugin was looking at, so that logs from
+    /// several clients or panes can be told apart.
+    fn write_log_line(&self, level: LogLevel, fields: &[(&str, String)], message: &str) {
+        let client_id = self
+            .current_client_id
+            .map_or("-".to_string(), |client_id| client_id.to_string());
+        let tab_position = match self.focused_tab_position() {
+            usize::MAX => "-".to_string(),
+            tab_position => tab_position.to_string(),
+        };
+        let pane_id = match self.latest_tab_pane.pane_id {
+            u32::MAX => "-".to_string(),
+            pane_id => pane_id.to_string(),
+        };
+        let line = match self.log_format {
+            LogFormat::Text => format!(
+                "[autolock] {} client={} tab={} pane={} {} {}",
+                log::format_timestamp(self.unix_seconds),
+                client_id,
+                tab_position,
+                pane_id,
+                format!("{:?}", level).to_uppercase(),
+                message
+            ),
+            LogFormat::Json => {
+                let event = self
+                    .current_event
+                    .map_or("Pipe".to_string(), |event_type| format!("{:?}", event_type));
+                let mut context = vec![
+                    ("client", client_id),
+                    ("tab", tab_position),
+                    ("focused_pane", pane_id),
+                ];
+                context.extend(fields.iter().cloned());
+                log::format_json(self.unix_seconds, level, &event, message, &context)
+            }
+        };
+        if self.log_to_plugin.is_some() {
+            self.forwarded_log_lines.borrow_mut().push(line.clone());
+        }
+        let written = self
+            .log_file
+            .as_ref()
+            .is_some_and(|log_file| log_file.borrow_mut().write_line(&line).is_ok());
+        if !written {
+            eprintln!("{}", line);
+        }
+    }
+
+    fn open_log_file(&mut self, log_file: &str) {
+        let path = host_path(log_file);
+        match LogFile::open(&path, self.log_file_max_bytes, self.log_file_keep) {
+            Ok(log_file) => self.log_file = Some(RefCell::new(log_file)),
+            Err(error) => {
+                self.log_file = None;
+                log!(self, Warn, "Cannot open log file `{}`: {}", path, error);
+            }
+        }
+    }
+
+    /// A setting that must be a finite, non-negative number. Anything else keeps the default, and
+    /// is reported rather than left to stop the plugin.
+    fn number_setting<T: std::str::FromStr + PartialOrd + Default>(
+        &mut self,
+        configuration: &BTreeMap<String, String>,
+        key: &str,
+    ) -> Option<T> {
+        let value = configuration.get(key)?;
+        match value.trim().parse::<T>() {
+            // Also rules out NaN; `inf` parses as a float, but would never let a timer fire.
+            Ok(number)
+                if number >= T::default()
+                    && value.trim().parse::<f64>().is_ok_and(f64::is_finite) =>
+            {
+                Some(number)
+            }
+            _ => {
+                self.configuration_errors.push(format!(
+                    "`{}` must be a finite, non-negative number, not `{}`; using the default.",
+                    key, value
+                ));
+                None
+            }
+        }
+    }
+
+    fn load_configuration(&mut self, configuration: BTreeMap<String, String>) {
+        if let Some(is_enabled) = configuration.get("is_enabled") {
+            self.is_enabled = matches!(is_enabled.trim(), "true" | "t" | "y" | "1");
+        }
+        if let Some(lock_trigger_cmds) = configuration.get("triggers") {
+            self.set_triggers(
+                lock_trigger_cmds
+                    .split('|')
+                    .map(|s| s.trim().to_string())
+                    .collect(),
+            );
+        }
+        if let Some(coordinate_instances) = configuration.get("coordinate_instances") {
+            self.coordinate_instances =
+                matches!(coordinate_instances.trim(), "true" | "t" | "y" | "1");
+        }
+        if let Some(embedded_sessions) = configuration.get("embedded_sessions") {
+            let embedded_sessions: Vec<String> = embedded_sessions
+                .split('|')
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect();
+            self.embedded_sessions = Triggers::compile(&embedded_sessions);
+        }
+        if let Some(overlay_plugins) = configuration.get("overlay_plugins") {
+            self.overlay_plugins = overlay_plugins
+                .split('|')
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect();
+        }
+        if let Some(presets) = configuration.get("presets") {
+            for preset in presets.split('|').map(str::trim) {
+                match preset {
+                    "secrets" => {
+                        let secret_triggers: Vec<String> = trigger::SECRETS_PRESET
+                            .iter()
+                            .map(|s| s.to_string())
+                            .collect();
+                        self.secret_triggers = Triggers::compile(&secret_triggers);
+                        let mut lock_trigger_cmds = self.lock_trigger_cmds.clone();
+                        for secret_trigger in secret_triggers {
+                            if !lock_trigger_cmds.contains(&secret_trigger) {
+                                lock_trigger_cmds.push(secret_trigger);
+                            }
+                        }
+                        self.set_triggers(lock_trigger_cmds);
+                    }
+                    "" => {}
+                    preset => self.configuration_errors.push(format!(
+                        "`presets` may only list `secrets`, not `{}`; ignoring it.",
+                        preset
+                    )),
+                }
+            }
+        }
+        if let Some(measure_latency) = configuration.get("measure_latency") {
+            self.latency =
+                matches!(measure_latency.trim(), "true" | "t" | "y" | "1").then(Latency::default);
+        }
+        if let Some(dry_run) = configuration.get("dry_run") {
+            self.dry_run = matches!(dry_run.trim(), "true" | "t" | "y" | "1");
+        }
+        if let Some(idle_lock_seconds) =
+            self.number_setting::<f64>(&configuration, "idle_lock_seconds")
+        {
+            self.idle_lock_seconds = Some(idle_lock_seconds).filter(|seconds| *seconds > 0.0);
+        }
+        if let Some(reaction_seconds) = self.number_setting(&configuration, "reaction_seconds") {
+            self.reaction_seconds = reaction_seconds;
+        }
+        if let Some(max_poll_seconds) = self.number_setting(&configuration, "max_poll_seconds") {
+            self.max_poll_seconds = max_poll_seconds;
+        }
+        if let Some(max_polls_per_second) =
+            self.number_setting(&configuration, "max_polls_per_second")
+        {
+            self.max_polls_per_second = max_polls_per_second;
+        }
+        self.poll_seconds = self.reaction_seconds;
+        if let Some(print_to_log) = configuration.get("print_to_log") {
+            // Older configurations only had this; it meant "everything that matters".
+            if matches!(print_to_log.trim(), "true" | "t" | "y" | "1") {
+                self.log_level = LogLevel::Debug;
+            }
+        }
+        if let Some(log_format) = configuration.get("log_format") {
+            match LogFormat::parse(log_format) {
+                Some(log_format) => self.log_format = log_format,
+                None => self.configuration_errors.push(format!(
+                    "`log_format` must be `text` or `json`, not `{}`; using the default.",
+                    log_format
+                )),
+            }
+        }
+        if let Some(log_level) = configuration.get("log_level") {
+            match LogLevel::parse(log_level) {
+                Some(log_level) => self.log_level = log_level,
+                None => self.configuration_errors.push(format!(
+                    "`log_level` must be off, error, warn, info, debug, or trace, not `{}`; using the default.",
+                    log_level
+                )),
+            }
+        }
+        if let Some(log_file_max_bytes) = self.number_setting(&configuration, "log_file_max_bytes")
+        {
+            self.log_file_max_bytes = log_file_max_bytes;
+        }
+        if let Some(log_file_keep) = self.number_setting(&configuration, "log_file_keep") {
+            self.log_file_keep = log_file_keep;
+        }
+        if let Some(is_inverse) = configuration.get("inverse") {
+            self.is_inverse = matches!(is_inverse.trim(), "true" | "t" | "y" | "1");
+        }
+        if let Some(on_lock) = configuration.get("on_lock") {
+            self.hooks.on_lock = Some(on_lock.clone());
+        }
+        if let Some(on_unlock) = configuration.get("on_unlock") {
+            self.hooks.on_unlock = Some(on_unlock.clone());
+        }
+        if let Some(osc_on_lock) = configuration.get("osc_on_lock") {
+            self.hooks.osc_on_lock = Some(osc_on_lock.clone());
+        }
+        if let Some(osc_on_unlock) = configuration.get("osc_on_unlock") {
+            self.hooks.osc_on_unlock = Some(osc_on_unlock.clone());
+        }
+        if let Some(osc_tty) = configuration.get("osc_tty") {
+            self.hooks.osc_tty = Some(osc_tty.trim().to_string());
+        }
+        if let Some(webhook_url) = configuration.get("webhook_url") {
+            self.webhook = Some(Webhook {
+                url: webhook_url.trim().to_string(),
+            });
+        }
+        if let Some(state_file) = configuration.get("state_file") {
+            self.state_file = Some(host_path(state_file.trim()));
+        }
+        if let Some(zjstatus_format) = configuration.get("zjstatus_format") {
+            self.zjstatus = Some(Zjstatus::new(zjstatus_format.clone()));
+        }
+        if let Some(log_to_plugin) = configuration.get("log_to_plugin") {
+            self.log_to_plugin = Some(log_to_plugin.trim().to_string());
+        }
+        if let Some(log_file) = configuration.get("log_file") {
+            self.open_log_file(log_file.trim());
+        }
+
+        log!(self, Info, "Configuration loaded.");
+        log!(self, Info, "Enabled: {}", self.is_enabled);
+        log!(self, Info, "Trigger commands: {:?}", self.lock_trigger_cmds);
+        log!(self, Info, "Reaction seconds: {}", self.reaction_seconds);
+        log!(self, Info, "Max poll seconds: {}", self.max_poll_seconds);
+        log!(
+            self,
+            Info,
+            "Max polls per second: {}",
+            self.max_polls_per_second
+        );
+    }
+    /// One `<key>: <value>` line per piece of state that scripts may want to branch on.
+    fn format_status(&self) -> String {
+        let locked_by = match (self.latest_mode, self.locked_by) {
+            (InputMode::Locked, Some(LockOrigin::Autolock)) => "autolock",
+            (InputMode::Locked, Some(LockOrigin::User)) => "user",
+            _ => "none",
+        };
+        [
+            format!("enabled: {}", self.is_enabled),
+            format!("dry_run: {}", self.dry_run),
+            format!("mode: {:?}", self.latest_mode).to_lowercase(),
+            format!("locked_by: {}", locked_by),
+            format!("mode_switches: {}", self.metrics.mode_switches),
+            format!("suppressed_switches: {}", self.metrics.suppressed_switches),
+            format!("list_clients_calls: {}", self.metrics.list_clients_calls),
+            format!("timer_fires: {}", self.metrics.timer_fires),
+            format!("matched_triggers: {}", self.metrics.matched_triggers),
+        ]
+        .join("\n")
+    }
+
+    fn run_hooks(&mut self, previous_mode: InputMode, input_mode: InputMode) {
+        if input_mode != InputMode::Locked && previous_mode != InputMode::Locked {
+            // Neither locking nor unlocking (e.g., from Normal to Pane).
+            return;
+        }
+        for command in self.hooks.commands(previous_mode, input_mode) {
+            self.actions.push(Action::RunCommand(command));
+        }
+        if let Some(webhook) = &self.webhook {
+            self.actions.push(Action::PostJson {
+                url: webhook.url.clone(),
+                body: webhook.body(
+                    input_mode,
+                    self.latest_trigger.clone(),
+                    self.latest_tab_pane.pane_id,
+                    self.unix_seconds,
+                ),
+            });
+        }
+    }
+
+    /// Tell zjstatus about changes to what it shows.
+    fn update_zjstatus(&mut self) {
+        let mode = self.expected_mode.unwrap_or(self.latest_mode);
+        let Some(zjstatus) = self.zjstatus.as_mut() else {
+            return;
+        };
+        if let Some(payload) =
+            zjstatus.update(mode, self.is_enabled, self.latest_trigger.as_deref())
+        {
+            self.actions.push(Action::PipeToPlugin {
+                plugin_url: None,
+                name: "zjstatus".to_string(),
+                payload,
+            });
+        }
+    }
+
+    /// Runtime changes from before a reload or restart override the configuration.
+    fn restore_persisted(&mut self) {
+        let Some(persist_file) = &self.persist_file else {
+            return;
+        };
+        let Ok(contents) = std::fs::read_to_string(persist_file) else {
+            return;
+        };
+        self.persisted = Persisted::parse(&contents);
+        if let Some(is_enabled) = self.persisted.is_enabled {
+            log!(self, Info, "Restored enabled: {}", is_enabled);
+            self.is_enabled = is_enabled;
+        }
+        if !self.persisted.added_triggers.is_empty() || !self.persisted.removed_triggers.is_empty()
+        {
+            log!(
+                self,
+                Info,
+                "Restored added triggers {:?} and removed triggers {:?}.",
+                self.persisted.added_triggers,
+                self.persisted.removed_triggers
+            );
+            self.apply_runtime_triggers();
+        }
+    }
+
+    /// Layer the triggers added and removed at runtime over the configured ones.
+    fn apply_runtime_triggers(&mut self) {
+        let mut lock_trigger_cmds = self.lock_trigger_cmds.clone();
+        for added_trigger in &self.persisted.added_triggers {
+            if !lock_trigger_cmds.contains(added_trigger) {
+                lock_trigger_cmds.push(added_trigger.clone());
+            }
+        }
+        lock_trigger_cmds.retain(|trigger| !self.persisted.removed_triggers.contains(trigger));
+        self.set_triggers(lock_trigger_cmds);
+        // The focused pane's command may have become a trigger, or stopped being one.
+        self.latest_assessment = None;
+    }
+
+    fn change_trigger(&mut self, trigger: String, is_added: bool) {
+        let (added, other) = if is_added {
+            (
+                &mut self.persisted.added_triggers,
+                &mut self.persisted.removed_triggers,
+            )
+        } else {
+            (
+                &mut self.persisted.removed_triggers,
+                &mut self.persisted.added_triggers,
+            )
+        };
+        other.retain(|other_trigger| *other_trigger != trigger);
+        if !added.contains(&trigger) {
+            added.push(trigger.clone());
+        }
+        log!(
+            self,
+            Info,
+            "{} trigger `{}`.",
+            if is_added { "Added" } else { "Removed" },
+            trigger
+        );
+        self.apply_runtime_triggers();
+        self.persist();
+    }
+
+    fn persist(&mut self) {
+        let Some(persist_file) = &self.persist_file else {
+            return;
+        };
+        if let Err(error) = std::fs::write(persist_file, self.persisted.format()) {
+            log!(
+                self,
+                Warn,
+                "Cannot remember state in `{}`: {}",
+                persist_file,
+                error
+            );
+            self.persist_file = None;
+        }
+    }
+
+    /// Written to a temporary file first and then renamed, so readers never see half of it.
+    fn update_state_file(&mut self) {
+        let Some(state_file) = &self.state_file else {
+            return;
+        };
+        let mode = self.expected_mode.unwrap_or(self.latest_mode);
+        let state = State {
+            mode: format!("{:?}", mode).to_lowercase(),
+            enabled: self.is_enabled,
+            trigger: self
+                .latest_trigger
+                .clone()
+                .filter(|_| self.is_enabled && mode == InputMode::Locked),
+        };
+        let contents = format!("{}\n", state.to_json());
+        if self.state_file_contents.as_ref() == Some(&contents) {
+            return;
+        }
+        let temporary_file = format!("{}.tmp", state_file);
+        let written = std::fs::write(&temporary_file, &contents)
+            .and_then(|_| std::fs::rename(&temporary_file, state_file));
+        match written {
+            Ok(()) => self.state_file_contents = Some(contents),
+            Err(error) => {
+                log!(
+                    self,
+                    Warn,
+                    "Cannot write state file `{}`: {}",
+                    state_file,
+                    error
+                );
+                // Trying again on every change would only repeat the warning.
+                self.state_file = None;
+            }
+        }
+    }
+
+    fn trace(&mut self, entry: TraceEntry) {
+        if self.trace.len() == TRACE_CAPACITY {
+            self.trace.pop_front();
+        }
+        self.trace.push_back((self.clock_seconds, entry));
+    }
+
+    /// Time spent locked and unlocked and the triggers that locked most often, one `key: value`
+    /// line each.
+    pub fn format_stats(&self) -> String {
+        let (mut locked_seconds, mut unlocked_seconds) =
+            (self.metrics.locked_seconds, self.metrics.unlocked_seconds);
+        let mode_seconds = self.clock_seconds - self.latest_mode_seconds;
+        if self.latest_mode == InputMode::Locked {
+            locked_seconds += mode_seconds;
+        } else {
+            unlocked_seconds += mode_seconds;
+        }
+        let total_seconds = locked_seconds + unlocked_seconds;
+        let mut trigger_locks: Vec<(&String, &u64)> = self.metrics.trigger_locks.iter().collect();
+        trigger_locks.sort_by(|a, b| b.1.cmp(a.1));
+        [
+            format!("locked_seconds: {:.0}", locked_seconds),
+            format!("unlocked_seconds: {:.0}", unlocked_seconds),
+            format!(
+                "locked_share: {:.0}%",
+                if total_seconds > 0.0 {
+                    100.0 * locked_seconds / total_seconds
+                } else {
+                    0.0
+                }
+            ),
+            format!("automatic_switches: {}", self.metrics.mode_switches),
+            format!(
+                "top_triggers: {}",
+                trigger_locks
+                    .iter()
+                    .take(5)
+                    .map(|(trigger, locks)| format!("{} {}", trigger, locks))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        ]
+        .join("\n")
+    }
+
+    /// One line per check, starting with `ok`, `FAIL`, or `--` for what is merely informative.
+    fn format_doctor(&self) -> String {
+        let check = |is_ok: bool, line: String| -> String {
+            format!("{}\t{}", if is_ok { "ok" } else { "FAIL" }, line)
+        };
+        let mut lines = vec![check(
+            self.permissions_granted,
+            if self.permissions_granted {
+                "permissions granted".to_string()
+            } else if self.permissions_denied {
+                "permissions denied; send `request-permissions` to ask again".to_string()
+            } else {
+                "permissions not answered yet".to_string()
+            },
+        )];
+        for event_type in &self.subscriptions {
+            let seen = match self.events_seen.get(event_type) {
+                Some(seen_seconds) => {
+                    format!("seen {:.1}s ago", self.clock_seconds - seen_seconds)
+                }
+                None => "never seen".to_string(),
+            };
+            let line = format!("event {:?} {}", event_type, seen);
+            lines.push(if ESSENTIAL_EVENT_TYPES.contains(event_type) {
+                check(self.events_seen.contains_key(event_type), line)
+            } else {
+                format!("--\t{}", line)
+            });
+        }
+        lines.push(check(
+            self.configuration_errors.is_empty(),
+            if self.configuration_errors.is_empty() {
+                "configuration understood".to_string()
+            } else {
+                format!("configuration: {}", self.configuration_errors.join(" "))
+            },
+        ));
+        lines.push(match self.latest_list_clients_answer {
+            Some((answer_seconds, is_usable)) => check(
+                is_usable,
+                format!(
+                    "list_clients {} the focused pane {:.1}s ago",
+                    if is_usable { "named" } else { "did not name" },
+                    self.clock_seconds - answer_seconds
+                ),
+            ),
+            None => check(false, "list_clients never answered".to_string()),
+        });
+        let mut problems = Vec::new();
+        for (index, trigger) in self.lock_trigger_cmds.iter().enumerate() {
+            if trigger.is_empty() {
+                problems.push("an empty trigger (e.g., from `||`)".to_string());
+            } else if trigger.chars().all(|c| c == '*') {
+                problems.push(format!("`{}` matches every command", trigger));
+            } else if SHELLS.contains(&trigger.as_str()) {
+                problems.push(format!(
+                    "`{}` is a shell, so it runs in most panes",
+                    trigger
+                ));
+            } else if self.lock_trigger_cmds[..index].contains(trigger) {
+                problems.push(format!("`{}` is listed twice", trigger));
+            }
+        }
+        lines.push(check(
+            problems.is_empty() && !self.lock_trigger_cmds.is_empty(),
+            if self.lock_trigger_cmds.is_empty() {
+                "no triggers".to_string()
+            } else if problems.is_empty() {
+                format!("{} triggers", self.lock_trigger_cmds.len())
+            } else {
+                format!("triggers: {}", problems.join("; "))
+            },
+        ));
+        lines.join("\n")
+    }
+
+    /// One `<seconds since load>\t<event or decision>` line per entry in the trace, oldest first.
+    fn format_trace(&self) -> String {
+        self.trace
+            .iter()
+            .map(|(clock_seconds, entry)| {
+                let entry = match entry {
+                    TraceEntry::Event(event_type) => format!("event {:?}", event_type),
+                    TraceEntry::Pipe(payload) => format!("pipe `{}`", payload),
+                    TraceEntry::Assessment {
+                        pane_id,
+                        command,
+                        is_trigger,
+                    } => format!(
+                        "pane {} runs `{}`{}",
+                        pane_id,
+                        command,
+                        if *is_trigger { " (trigger)" } else { "" }
+                    ),
+                    TraceEntry::Switch(input_mode) => format!("switch to {:?}", input_mode),
+                    TraceEntry::SuppressedSwitch(input_mode) => {
+                        format!("no switch to {:?}", input_mode)
+                    }
+                };
+                format!("{:.3}\t{}", clock_seconds, entry)
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// One `<pane_id>\t<command>` line per terminal pane in the focused tab.
+    fn format_detected_commands(&self) -> String {
+        self.latest_pane_manifest
+            .panes
+            .get(&self.focused_tab_position())
+            .map(|panes| {
+                panes
+                    .iter()
+                    .filter(|pane| !pane.is_plugin)
+                    .map(|pane| {
+                        let command = self
+                            .detected_commands
+                            .get(&pane.id)
+                            .map_or("N/A".to_string(), |command| self.redacted(command));
+                        format!("{}\t{}", pane.id, command)
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            })
+            .unwrap_or_default()
+    }
+
+    /// Reply to a `zellij pipe` invocation; keybinding messages have nowhere to reply to.
+    fn pipe_output(&mut self, pipe_message: &PipeMessage, output: &str) {
+        if let PipeSource::Cli(_) = pipe_message.source {
+            self.actions.push(Action::PipeOutput {
+                name: pipe_message.name.clone(),
+                output: format!("{}\n", output),
+            });
+        }
+    }
+
+    /// Remember whether a trigger was the first thing ever seen running in a pane, i.e., the pane
+    /// was created for it or its shell `exec`ed into it. Such a pane never returns to a shell;
+    /// it closes when the trigger exits.
+    fn observe_pane_command(&mut self, pane_id: u32, running_command: &str) {
+        let is_trigger_cmd = running_command != "N/A" && self.is_trigger_cmd(running_command);
+        let is_root_trigger = self
+            .root_trigger_panes
+            .entry(pane_id)
+            .or_insert(is_trigger_cmd);
+        if !is_trigger_cmd {
+            *is_root_trigger = false;
+        }
+    }
+
+    /// Remember a pane whose title has been seen to change along with its command. Renames of such
+    /// a pane arrive in a `PaneUpdate`, so it only needs a slow poll in case its titles stop
+    /// following its commands (e.g., a program that sets its own).
+    fn observe_pane_title(&mut self, pane_id: u32, running_command: &str) {
+        // A title that merely names the first command seen, e.g., the shell, may never change.
+        let command_changed = self
+            .detected_commands
+            .get(&pane_id)
+            .is_some_and(|previous_command| previous_command != running_command);
+        if self.titled_panes.contains(&pane_id) || !command_changed {
+            return;
+        }
+        let Some(pane) = get_terminal_pane(pane_id, &self.latest_pane_manifest) else {
+            return;
+        };
+        let title = command::sanitize(&pane.title);
+        if title == running_command
+            || command::executable(&title) == command::executable(running_command)
+        {
+            log!(
+                self,
+                Info,
+                "Pane {} titles follow its command; polling it less often.",
+                pane_id
+            );
+            self.titled_panes.insert(pane_id);
+        }
+    }
+
+    /// Whether events alone tell us when the command in the focused pane changes.
+    fn is_focused_pane_event_driven(&self) -> bool {
+        let pane_id = self.latest_tab_pane.pane_id;
+        self.latest_tab_pane.pane_is_plugin
+            || self.command_panes.contains_key(&pane_id)
+            || self.editor_panes.contains(&pane_id)
+            || self.shell_panes.contains_key(&pane_id)
+    }
+
+    /// Whether events tell us about most changes of the focused pane's command, leaving only a slow
+    /// poll in case they stop: renames for panes whose titles follow their commands, and
+    /// `PaneClosed` for panes rooted in a trigger, whose command only ends with the pane.
+    fn is_focused_pane_slow_changing(&self) -> bool {
+        let pane_id = self.latest_tab_pane.pane_id;
+        !self.latest_tab_pane.pane_is_plugin
+            && (self.titled_panes.contains(&pane_id)
+                || self.root_trigger_panes.get(&pane_id) == Some(&true))
+    }
+
+    fn is_focused_pane(&self, pane: &PaneInfo) -> bool {
+        pane.id == self.latest_tab_pane.pane_id
+            && pane.is_plugin == self.latest_tab_pane.pane_is_plugin
+    }
+
+    /// Match the tabs of a new pane manifest to the tabs we already know by the panes they share,
+    /// keeping their ids stable while their positions change.
+    fn track_tabs(&mut self, pane_manifest: &PaneManifest) {
+        let mut previous_tabs: Vec<(usize, TrackedTab)> =
+            std::mem::take(&mut self.tabs).into_iter().collect();
+        let mut positions: Vec<usize> = pane_manifest.panes.keys().copied().collect();
+        positions.sort_unstable();
+        for position in positions {
+            let pane_ids: BTreeSet<(u32, bool)> = pane_manifest.panes[&position]
+                .iter()
+                .map(|pane| (pane.id, pane.is_plugin))
+                .collect();
+            let previous_tab = previous_tabs
+                .iter()
+                .enumerate()
+                .map(|(index, (_, tab))| (index, tab.pane_ids.intersection(&pane_ids).count()))
+                .filter(|(_, shared_panes)| *shared_panes > 0)
+                .max_by_key(|(_, shared_panes)| *shared_panes)
+                .map(|(index, _)| index)
+                .or_else(|| {
+                    // A tab we have only seen in a TabUpdate so far.
+                    previous_tabs.iter().position(|(previous_position, tab)| {
+                        *previous_position == position && tab.pane_ids.is_empty()
+                    })
+                });
+            let (id, name) = match previous_tab {
+                Some(index) => {
+                    let (_, tab) = previous_tabs.remove(index);
+                    (tab.id, tab.name)
+                }
+                None => (self.new_tab_id(), None),
+            };
+            self.tabs
+                .insert(position, TrackedTab { id, pane_ids, name });
+        }
+    }
+
+    /// The id of the tab at a position, tracking it if it is not in a pane manifest yet.
+    fn tab_id_at(&mut self, position: usize) -> u32 {
+        if let Some(tab) = self.tabs.get(&position) {
+            return tab.id;
+        }
+        let id = self.new_tab_id();
+        self.tabs.insert(
+            position,
+            TrackedTab {
+                id,
+                pane_ids: BTreeSet::new(),
+                name: None,
+            },
+        );
+        id
+    }
+
+    /// Remember the names from a `TabUpdate` whose positions agree with the tracked tabs.
+    fn name_tabs(&mut self, tab_names: Vec<String>) {
+        for (position, name) in tab_names.into_iter().enumerate() {
+            if let Some(tab) = self.tabs.get_mut(&position) {
+                tab.name = Some(name);
+            }
+        }
+    }
+
+    fn new_tab_id(&mut self) -> u32 {
+        let id = self.next_tab_id;
+        self.next_tab_id = self.next_tab_id.wrapping_add(1);
+        id
+    }
+
+    /// If the focused pane left its tab (`break-pane`, `move-pane-to-tab`) and zellij has not told
+    /// us about a newly focused tab, the pane took the focus along with it.
+    fn follow_moved_pane(&mut self, previous_tab_position: usize, pane_manifest: &PaneManifest) {
+        let tab_position = self.focused_tab_position();
+        let pane_in_tab = |position: &usize| {
+            pane_manifest
+                .panes
+                .get(position)
+                .is_some_and(|panes| panes.iter().any(|pane| self.is_focused_pane(pane)))
+        };
+        let was_in_tab = self
+            .latest_pane_manifest
+            .panes
+            .get(&previous_tab_position)
+            .is_some_and(|panes| panes.iter().any(|pane| self.is_focused_pane(pane)));
+        if !was_in_tab || pane_in_tab(&tab_position) {
+            return;
+        }
+        let new_position = pane_manifest
+            .panes
+            .keys()
+            .find(|position| pane_in_tab(position))
+            .copied();
+        if let Some(position) = new_position {
+            log!(
+                self,
+                Info,
+                "Pane {} moved to tab {}.",
+                self.latest_tab_pane.pane_id,
+                position
+            );
+            self.latest_tab_pane.tab_id = self.tab_id_at(position);
+            // Same pane, same command: re-apply its mode rather than treating it as new.
+            self.assess_focused_pane();
+        }
+    }
+
+    /// The focused tab as far as a pane manifest alone can tell: the only tab there is, or the tab
+    /// containing the pane the current client was last seen in.
+    fn infer_focused_tab(&self, pane_manifest: &PaneManifest) -> Option<usize> {
+        if pane_manifest.panes.len() == 1 {
+            return pane_manifest.panes.keys().next().copied();
+        }
+        pane_manifest
+            .panes
+            .iter()
+            .find(|(_, panes)| panes.iter().any(|pane| self.is_focused_pane(pane)))
+            .map(|(position, _)| *position)
+    }
+
+    /// The current position of the focused tab, to look it up in a pane manifest.
+    fn focused_tab_position(&self) -> usize {
+        self.tabs
+            .iter()
+            .find(|(_, tab)| tab.id == self.latest_tab_pane.tab_id)
+            .map_or(usize::MAX, |(position, _)| *position)
+    }
+
+    /// Whether the pane manifest places a terminal pane in a tab other than the focused one.
+    fn is_in_background_tab(&self, pane_id: u32) -> bool {
+        self.latest_pane_manifest
+            .panes
+            .iter()
+            .find(|(_, panes)| {
+                panes
+                    .iter()
+                    .any(|pane| !pane.is_plugin && pane.id == pane_id)
+            })
+            .is_some_and(|(tab_pos, _)| *tab_pos != self.focused_tab_position())
+    }
+
+    fn is_focused_terminal_pane(&self, pane_id: u32) -> bool {
+        pane_id == self.latest_tab_pane.pane_id && !self.latest_tab_pane.pane_is_plugin
+    }
+
+    fn set_focused_pane(&mut self, pane: PaneInfo) {
+        self.latest_tab_pane.pane_id = pane.id;
+        self.latest_tab_pane.pane_is_plugin = pane.is_plugin;
+        self.latest_tab_pane.pane_is_fullscreen = pane.is_fullscreen;
+        self.latest_tab_pane.pane_title = pane.title;
+    }
+
+    /// Forget everything learned about the previous session's tabs, panes, and commands.
+    fn reset_session_state(&mut self) {
+        self.reset_client_state();
+        self.tabs.clear();
+        self.focused_tab_hint = None;
+        self.command_panes.clear();
+        self.root_trigger_panes.clear();
+        self.replaced_panes.clear();
+        self.titled_panes.clear();
+        self.editor_panes.clear();
+        self.shell_panes.clear();
+    }
+
+    /// Decisions wait for a consistent snapshot: the focused tab, located in a pane manifest, and
+    /// its focused pane. Returns whether this call completed initialization.
+    fn try_initialize(&mut self) -> bool {
+        if self.is_initialized
+            || self.latest_tab_pane.pane_id == u32::MAX
+            || self.focused_tab_position() == usize::MAX
+        {
+            return false;
+        }
+        self.is_initialized = true;
+        log!(
+            self,
+            Info,
+            "Initialized: tab {}, pane {}.",
+            self.focused_tab_position(),
+            self.latest_tab_pane.pane_id
+        );
+        true
+    }
+
+    /// Forget what the previous client was focused on and running.
+    fn reset_client_state(&mut self) {
+        self.is_initialized = false;
+        self.latest_tab_pane = TabPane {
+            tab_id: u32::MAX,
+            pane_id: u32::MAX,
+            pane_is_plugin: false,
+            pane_is_fullscreen: false,
+            pane_title: "".to_string(),
+        };
+        self.latest_assessment = None;
+        self.detected_commands.clear();
+    }
+
+    /// Re-initialize focus bookkeeping from a freshly (re)attached client.
+    fn attach_client(&mut self, client: &ClientInfo) {
+        log!(self, Info, "Client {} attached.", client.client_id);
+        self.reset_client_state();
+        self.current_client_id = Some(client.client_id);
+        let (pane_id, is_plugin) = match client.pane_id {
+            PaneId::Terminal(pane_id) => (pane_id, false),
+            PaneId::Plugin(pane_id) => (pane_id, true),
+        };
+        if let Some((tab_pos, pane)) = self
+            .latest_pane_manifest
+            .panes
+            .iter()
+            .flat_map(|(tab_pos, panes)| panes.iter().map(move |pane| (*tab_pos, pane)))
+            .find(|(_, pane)| pane.id == pane_id && pane.is_plugin == is_plugin)
+            .map(|(tab_pos, pane)| (tab_pos, pane.clone()))
+        {
+            self.latest_tab_pane.tab_id = self.tab_id_at(tab_pos);
+            self.set_focused_pane(pane);
+        } else {
+            self.latest_tab_pane.pane_id = pane_id;
+            self.latest_tab_pane.pane_is_plugin = is_plugin;
+        }
+    }
+
+    /// The command of a terminal pane as known to the pane manifest: the command of a command
+    /// pane, or else its title (which many shells set to the running command).
+    fn manifest_command(&self, pane_id: u32) -> Option<String> {
+        let pane = get_terminal_pane(pane_id, &self.latest_pane_manifest)?;
+        if pane.exited {
+            return None;
+        }
+        let command = command::sanitize(pane.terminal_command.as_ref().unwrap_or(&pane.title));
+        if command.is_empty() {
+            None
+        } else {
+            Some(command)
+        }
+    }
+
+    /// Command panes report their own lifecycle, so only plain shell panes need `list_clients`.
+    fn assess_focused_pane(&mut self) {
+        if !self.is_enabled
+            || !self.is_visible
+            || !self.is_initialized
+            || !self.is_active_instance()
+            || self.is_session_settling()
+            || self.is_unlock_requested()
+        {
+            return;
+        }
+        if self.latest_tab_pane.pane_is_plugin {
+            log!(self, Debug, "Plugin pane focused; skipping.");
+            return;
+        }
+        let pane_id = self.latest_tab_pane.pane_id;
+        if self.editor_panes.contains(&pane_id) {
+            log!(
+                self,
+                Debug,
+                "Editor active in pane {}; staying locked.",
+                pane_id
+            );
+            self.apply_trigger(pane_id, "editor".to_string(), true);
+        } else if let Some(running_command) = self.shell_panes.get(&pane_id) {
+            let running_command = running_command.clone().unwrap_or("N/A".to_string());
+            self.assess_running_command(pane_id, running_command);
+        } else if self.command_panes.contains_key(&pane_id) {
+            self.assess_command_pane(pane_id);
+        } else {
+            self.list_clients();
+        }
+    }
+
+    /// Inside an IDE's terminal, the IDE already manages keybindings; `enable` still works for
+    /// anyone who wants autolock there after all.
+    fn turn_off_when_embedded(&mut self, reason: &str) {
+        if self.is_enabled {
+            log!(
+                self,
+                Info,
+                "Session is embedded in an IDE terminal ({}); disabled.",
+                reason
+            );
+            self.is_enabled = false;
+        }
+    }
+
+    /// The pane a message is about: the one named by the sender, or else the focused one.
+    fn sender_pane_id(&self, pipe_message: &PipeMessage) -> u32 {
+        pipe_message
+            .args
+            .get(PANE_ID_ARG)
+            .and_then(|pane_id| pane_id.trim().parse::<u32>().ok())
+            .unwrap_or(self.latest_tab_pane.pane_id)
+    }
+
+    /// Shells hooked up to autolock say exactly what runs in their pane, and when, so from the
+    /// first report on, their pane is decided on from those rather than by polling.
+    fn shell_reported(&mut self, pane_id: u32, running_command: Option<String>) {
+        log!(
+            self,
+            Debug,
+            [pane = pane_id],
+            "Shell in pane {} reported: {:?}",
+            pane_id,
+            running_command
+                .as_deref()
+                .map(|running_command| self.redacted(running_command))
+        );
+        match &running_command {
+            Some(running_command) => {
+                self.detected_commands
+                    .insert(pane_id, running_command.clone());
+            }
+            None => {
+                self.detected_commands.remove(&pane_id);
+            }
+        }
+        self.shell_panes.insert(pane_id, running_command);
+    }
+
+    /// Editors that announce themselves save us from polling for them: locked on `editor-enter`,
+    /// and decided on again as soon as `editor-leave` arrives.
+    fn editor_handshake(&mut self, pane_id: u32, is_entering: bool) {
+        log!(
+            self,
+            Info,
+            [pane = pane_id],
+            "Editor {} pane {}.",
+            if is_entering { "entered" } else { "left" },
+            pane_id
+        );
+        if is_entering {
+            self.editor_panes.insert(pane_id);
+        } else if self.editor_panes.remove(&pane_id) {
+            // A poll made as the editor exits may still find it running; take that for
+            // the command already decided on rather than locking again.
+            let running_command = self
+                .detected_commands
+                .get(&pane_id)
+                .cloned()
+                .unwrap_or_default();
+            if self.is_enabled && self.is_focused_terminal_pane(pane_id) {
+                self.apply_trigger(pane_id, running_command.clone(), false);
+            }
+            self.latest_assessment = Some((pane_id, running_command));
+        }
+    }
+
+    /// Whether this instance is the one that switches modes: unless coordinating, always;
+    /// otherwise, when no other instance heard from lately has a lower id.
+    fn is_active_instance(&mut self) -> bool {
+        let (true, Some(instance_id)) = (self.coordinate_instances, self.instance_id) else {
+            return true;
+        };
+        let expired = self.instances.expire(self.clock_seconds);
+        for peer_id in &expired {
+            log!(self, Info, "Lost instance {}.", peer_id);
+        }
+        if !expired.is_empty() {
+            self.compile_triggers();
+        }
+        match self.instances.leader(instance_id) {
+            Some(peer_id) => {
+                log!(self, Debug, "Leaving decisions to instance {}.", peer_id);
+                false
+            }
+            None => true,
+        }
+    }
+
+    fn say_hello(&mut self) {
+        let (true, Some(instance_id)) = (self.coordinate_instances, self.instance_id) else {
+            return;
+        };
+        let hello = self
+            .instances
+            .hello(instance_id, &self.lock_trigger_cmds, self.clock_seconds);
+        if let Some(payload) = hello {
+            self.actions.push(Action::PipeToPlugin {
+                plugin_url: None,
+                name: INSTANCE_MESSAGE_NAME.to_string(),
+                payload,
+            });
+        }
+    }
+
+    /// The URL of an overlay plugin open in the focused tab, if any.
+    fn open_overlay(&self) -> Option<String> {
+        let panes = self
+            .latest_pane_manifest
+            .panes
+            .get(&self.focused_tab_position())?;
+        panes
+            .iter()
+            .filter(|pane| pane.is_plugin && !pane.is_suppressed)
+            .filter(|pane| !pane.is_floating || self.floating_panes_visible)
+            .filter_map(|pane| pane.plugin_url.as_ref())
+            .find(|plugin_url| {
+                self.overlay_plugins
+                    .iter()
+                    .any(|overlay| plugin_url.contains(overlay.as_str()))
+            })
+            .cloned()
+    }
+
+    /// For switches made for a reason other than what the focused pane runs.
+    fn focused_pane_switch_reason(&self, rule: &str) -> SwitchReason {
+        let pane_id = self.latest_tab_pane.pane_id;
+        SwitchReason {
+            pane_id,
+            command: self
+                .detected_commands
+                .get(&pane_id)
+                .map(|command| self.redacted(command))
+                .unwrap_or_default(),
+            rule: rule.to_string(),
+            event: self
+                .current_event
+                .map_or("pipe".to_string(), |event_type| format!("{:?}", event_type)),
+        }
+    }
+
+    fn undo(&mut self) {
+        let Some(latest_switch) = self.latest_switch.take() else {
+            log!(self, Info, "No switch to undo.");
+            return;
+        };
+        if self.expected_mode.unwrap_or(self.latest_mode) != latest_switch.to {
+            log!(
+                self,
+                Info,
+                "The mode changed since the latest switch; not undoing it."
+            );
+            return;
+        }
+        log!(
+            self,
+            Info,
+            "Undoing the switch from {:?} to {:?}.",
+            latest_switch.from,
+            latest_switch.to
+        );
+        self.undone_assessment = latest_switch.assessment;
+        self.pending_switch_reason = Some(self.focused_pane_switch_reason("undo"));
+        self.switch_to_input_mode(latest_switch.from);
+    }
+
+    fn request_unlock(&mut self, seconds: f64) {
+        let current_mode = self.expected_mode.unwrap_or(self.latest_mode);
+        let restore_mode = match self.unlocked_until {
+            Some((_, restore_mode)) => restore_mode,
+            None if current_mode == InputMode::Locked => current_mode,
+            None => {
+                log!(self, Debug, "Unlock requested, but not locked.");
+                return;
+            }
+        };
+        log!(
+            self,
+            Info,
+            "Unlocking for {} seconds, as requested.",
+            seconds
+        );
+        self.unlocked_until = Some((self.clock_seconds + seconds, restore_mode));
+        self.pending_switch_reason = Some(self.focused_pane_switch_reason("request-unlock"));
+        self.switch_to_input_mode(InputMode::Normal);
+        self.schedule_timer(seconds);
+    }
+
+    /// Whether a `request-unlock` still holds off decisions; once it runs out, the mode from
+    /// before it is restored, and deciding picks up from there.
+    fn is_unlock_requested(&mut self) -> bool {
+        match self.unlocked_until {
+            Some((until, _)) if self.clock_seconds < until => {
+                self.schedule_timer(until - self.clock_seconds);
+                true
+            }
+            Some((_, restore_mode)) => {
+                log!(self, Info, "Requested unlock ran out.");
+                self.unlocked_until = None;
+                self.latest_assessment = None;
+                self.pending_switch_reason =
+                    Some(self.focused_pane_switch_reason("request-unlock ran out"));
+                self.switch_to_input_mode(restore_mode);
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Once an unconfirmed switch is overdue, go back to the mode zellij last reported, and
+    /// decide again from there.
+    fn check_drift(&mut self) {
+        let Some(expected_mode) = self.expected_mode else {
+            return;
+        };
+        if self.pending_mode_switch.is_some() {
+            return;
+        }
+        if let Some(seconds_left) = self.drift.seconds_left(self.clock_seconds) {
+            // Another timer came first; the focused pane may not be polled, so check again when
+            // the switch is due.
+            self.schedule_timer(seconds_left);
+            return;
+        }
+        log!(
+            self,
+            Warn,
+            "Zellij never confirmed the switch to {:?}; it is still in {:?} as far as we know.",
+            expected_mode,
+            self.latest_mode
+        );
+        self.expected_mode = None;
+        self.latest_assessment = None;
+        self.assess_focused_pane();
+    }
+
+    /// Lock once input has been idle for `idle_lock_seconds`, or check again when it would be.
+    fn watch_idle(&mut self) {
+        let idle_lock_seconds = match self.idle_lock_seconds {
+            Some(idle_lock_seconds) if self.idle_locked.is_none() => idle_lock_seconds,
+            _ => return,
+        };
+        let idle_seconds = self.clock_seconds - self.latest_input_seconds;
+        if idle_seconds < idle_lock_seconds {
+            self.schedule_timer(idle_lock_seconds - idle_seconds);
+            return;
+        }
+        let current_mode = self.expected_mode.unwrap_or(self.latest_mode);
+        log!(
+            self,
+            Info,
+            "No input for {} seconds; locking until the next.",
+            idle_lock_seconds
+        );
+        self.idle_locked = Some(current_mode);
+        if current_mode != InputMode::Locked {
+            self.pending_switch_reason = Some(self.focused_pane_switch_reason("idle"));
+            self.switch_to_input_mode(InputMode::Locked);
+        }
+    }
+
+    fn assess_command_pane(&mut self, pane_id: u32) {
+        let (running_command, is_editor) = match self.command_panes.get(&pane_id) {
+            Some(command_pane) if command_pane.is_running && command_pane.is_editor => {
+                (command::sanitize(&command_pane.command), true)
+            }
+            Some(command_pane) if command_pane.is_running && !command_pane.command.is_empty() => {
+                (command::sanitize(&command_pane.command), false)
+            }
+            Some(_) => ("N/A".to_string(), false),
+            None => return,
+        };
+        log!(
+            self,
+            Debug,
+            [pane = pane_id, command = self.redacted(&running_command)],
+            "{} pane {} reported: `{}`",
+            if is_editor { "Editor" } else { "Command" },
+            pane_id,
+            self.redacted(&running_command)
+        );
+        self.detected_commands
+            .insert(pane_id, running_command.clone());
+        self.observe_pane_command(pane_id, &running_command);
+        if is_editor {
+            if self.is_enabled {
+                self.apply_trigger(pane_id, running_command, true);
+            }
+        } else {
+            self.assess_running_command(pane_id, running_command);
+        }
+    }
+
+    fn assess_running_command(&mut self, pane_id: u32, running_command: String) {
+        if !self.is_enabled {
+            return;
+        }
+
+        let mut is_trigger_cmd = false;
+
+        if running_command != "N/A" {
+            let running_command_exe = command::executable(&running_command);
+
+            is_trigger_cmd = self.is_trigger_cmd(&running_command);
+
+            log!(
+                self,
+                Debug,
+                [
+                    pane = pane_id,
+                    command = self.redacted(&running_command),
+                    trigger = is_trigger_cmd
+                ],
+                "Detected command: `{}`; Executable: `{}`; Is trigger? {}.",
+                self.redacted(&running_command),
+                running_command_exe,
+                is_trigger_cmd,
+            );
+        } else {
+            log!(self, Debug, [pane = pane_id], "No command detected.");
+        }
+
+        self.apply_trigger(pane_id, running_command, is_trigger_cmd);
+    }
+
+    fn set_triggers(&mut self, lock_trigger_cmds: Vec<String>) {
+        self.lock_trigger_cmds = lock_trigger_cmds;
+        self.compile_triggers();
+        // Tell the other instances about the change.
+        self.instances.hello_soon();
+    }
+
+    /// Whichever instance switches modes goes by the triggers of all of them, so that each
+    /// instance's configuration counts.
+    fn compile_triggers(&mut self) {
+        let mut lock_trigger_cmds = self.lock_trigger_cmds.clone();
+        for lock_trigger_cmd in self.instances.lock_trigger_cmds() {
+            if !lock_trigger_cmds.contains(lock_trigger_cmd) {
+                lock_trigger_cmds.push(lock_trigger_cmd.clone());
+            }
+        }
+        self.triggers = Triggers::compile(&lock_trigger_cmds);
+        // The focused pane's command may have become a trigger, or stopped being one.
+        self.latest_assessment = None;
+    }
+
+    fn is_trigger_cmd(&self, running_command: &str) -> bool {
+        self.matched_trigger(running_command).is_some()
+    }
+
+    /// The trigger matched by the command, or by any command it chains or pipes into.
+    fn matched_trigger(&self, running_command: &str) -> Option<String> {
+        self.triggers
+            .matching_command(running_command)
+            .map(str::to_string)
+    }
+
+    /// A command line as it may be logged: secret tools' arguments (e.g., the entry `pass show`
+    /// reveals) are left out.
+    fn redacted(&self, running_command: &str) -> String {
+        if self
+            .secret_triggers
+            .matching_command(running_command)
+            .is_some()
+        {
+            format!("{} [redacted]", command::executable(running_command))
+        } else {
+            running_command.to_string()
+        }
+    }
+
+    fn apply_trigger(&mut self, pane_id: u32, running_command: String, is_trigger_cmd: bool) {
+        if !self.is_active_instance() {
+            // Decide afresh should this instance take over.
+            self.latest_assessment = None;
+            return;
+        }
+        if let Some(overlay) = self.open_overlay() {
+            log!(self, Debug, "Overlay `{}` is open; not switching.", overlay);
+            // Decide afresh once it closes and focus returns.
+            self.latest_assessment = None;
+            return;
+        }
+        if self.idle_locked.is_some() {
+            log!(self, Debug, "Idle; staying locked until input.");
+            self.latest_assessment = None;
+            return;
+        }
+        if let Some(undone_assessment) = &self.undone_assessment {
+            if *undone_assessment == (pane_id, running_command.clone()) {
+                log!(
+                    self,
+                    Debug,
+                    "The switch for pane {} was undone; leaving the mode alone.",
+                    pane_id
+                );
+                return;
+            }
+            // Something changed, so the heuristic gets another go.
+            self.undone_assessment = None;
+        }
+        if let Some(latency) = self.latency.as_mut() {
+            latency.detected(self.clock_seconds);
+        }
+        self.latest_trigger = if is_trigger_cmd {
+            Some(
+                self.matched_trigger(&running_command)
+                    .unwrap_or_else(|| "editor".to_string()),
+            )
+        } else {
+            None
+        };
+        // Until zellij acknowledges a switch we made, the mode we asked for is the current one.
+        let current_mode = self.expected_mode.unwrap_or(self.latest_mode);
+
+        let target_input_mode = if is_trigger_cmd {
+            InputMode::Locked
+        } else if current_mode == InputMode::Locked && !self.is_inverse {
+            InputMode::Normal
+        } else {
+            current_mode
+        };
+
+        if current_mode != target_input_mode
+            && (current_mode == InputMode::Locked || current_mode == InputMode::Normal)
+        {
+            log!(
+                self,
+                Debug,
+                [
+                    pane = pane_id,
+                    command = self.redacted(&running_command),
+                    decision = format!("{:?}", target_input_mode).to_lowercase()
+                ],
+                "Switching to {:?} mode.",
+                target_input_mode
+            );
+            let rule = if !is_trigger_cmd {
+                "no trigger".to_string()
+            } else if let Some(trigger) = self.matched_trigger(&running_command) {
+                format!("trigger `{}`", trigger)
+            } else {
+                "editor".to_string()
+            };
+            self.pending_switch_reason = Some(SwitchReason {
+                pane_id,
+                command: self.redacted(&running_command),
+                rule,
+                event: self
+                    .current_event
+                    .map_or("pipe".to_string(), |event_type| format!("{:?}", event_type)),
+            });
+            self.switch_to_input_mode(target_input_mode);
+        } else if current_mode != target_input_mode {
+            log!(
+                self,
+                Debug,
+                "Leaving {:?} mode alone rather than switching to {:?}.",
+                current_mode,
+                target_input_mode
+            );
+            self.metrics.suppressed_switches += 1;
+            self.trace(TraceEntry::SuppressedSwitch(target_input_mode));
+        }
+
+        // Keyed by pane, so moving between two panes running the same command counts as a change.
+        let assessment = Some((pane_id, running_command.clone()));
+        if assessment != self.latest_assessment {
+            self.trace(TraceEntry::Assessment {
+                pane_id,
+                command: self.redacted(&running_command),
+                is_trigger: is_trigger_cmd,
+            });
+            self.latest_assessment = assessment;
+            if is_trigger_cmd {
+                self.metrics.matched_triggers += 1;
+            }
+            self.start_polling();
+        } else {
+            self.back_off_polling();
+        }
+    }
+
+    /// Nothing changed, so it is likely to stay that way for a while.
+    fn back_off_polling(&mut self) {
+        if self.is_focused_pane_event_driven() {
+            return;
+        }
+        if self.is_focused_pane_slow_changing() {
+            self.poll_seconds = self.max_poll_seconds;
+            self.schedule_timer(self.poll_seconds);
+        } else {
+            self.poll_seconds = (self.poll_seconds * 2.0)
+                .min(self.max_poll_seconds)
+                .max(self.reaction_seconds);
+            self.schedule_timer(self.poll_seconds);
+        }
+    }
+
+    /// Held back until the actions are taken, so that several decisions made in response to the
+    /// same event settle into a single switch.
+    fn switch_to_input_mode(&mut self, input_mode: InputMode) {
+        let previous_expected_mode = match self.pending_mode_switch {
+            Some((_, previous_expected_mode)) => previous_expected_mode,
+            None => self.expected_mode,
+        };
+        self.pending_mode_switch = Some((input_mode, previous_expected_mode));
+        self.expected_mode = Some(input_mode);
+    }
+
+    /// Request permissions and subscribe to events. Re-running this is harmless, which makes it
+    /// the way to recover after zellij stops answering us (e.g., a plugin reload or server hiccup).
+    fn subscribe(&mut self) {
+        let mut permissions = vec![
+            PermissionType::ChangeApplicationState,
+            PermissionType::ReadApplicationState,
+        ];
+        if self.log_to_plugin.is_some() || self.zjstatus.is_some() || self.coordinate_instances {
+            permissions.push(PermissionType::MessageAndLaunchOtherPlugins);
+        }
+        if self.hooks.is_set() {
+            permissions.push(PermissionType::RunCommands);
+        }
+        if self.webhook.is_some() {
+            permissions.push(PermissionType::WebAccess);
+        }
+        self.actions.push(Action::RequestPermissions(permissions));
+        self.subscriptions = self.needed_event_types();
+        self.actions
+            .push(Action::Subscribe(self.subscriptions.clone()));
+        self.unanswered_list_clients = 0;
+    }
+
+    /// Subscribe to what we need now, and unsubscribe from what we no longer need.
+    fn update_subscriptions(&mut self) {
+        let needed_event_types = self.needed_event_types();
+        let subscribe: Vec<EventType> = needed_event_types
+            .iter()
+            .filter(|event_type| !self.subscriptions.contains(event_type))
+            .copied()
+            .collect();
+        let unsubscribe: Vec<EventType> = self
+            .subscriptions
+            .iter()
+            .filter(|event_type| !needed_event_types.contains(event_type))
+            .copied()
+            .collect();
+        if !subscribe.is_empty() {
+            log!(self, Debug, "Subscribing to {:?}", subscribe);
+            self.actions.push(Action::Subscribe(subscribe));
+        }
+        if !unsubscribe.is_empty() {
+            log!(self, Debug, "Unsubscribing from {:?}", unsubscribe);
+            self.actions.push(Action::Unsubscribe(unsubscribe));
+        }
+        self.subscriptions = needed_event_types;
+    }
+
+    /// Input only matters while enabled, and keys only while asking to retry permissions.
+    fn needed_event_types(&self) -> Vec<EventType> {
+        let mut event_types = vec![
+            EventType::CommandPaneExited,
+            EventType::CommandPaneOpened,
+            EventType::CommandPaneReRun,
+            EventType::EditPaneExited,
+            EventType::EditPaneOpened,
+            EventType::ListClients,
+            EventType::ModeUpdate,
+            EventType::PaneClosed,
+            EventType::PaneUpdate,
+            EventType::PermissionRequestResult,
+            EventType::SessionUpdate,
+            EventType::TabUpdate,
+            EventType::Timer,
+            EventType::Visible,
+        ];
+        if self.is_enabled {
+            event_types.push(EventType::InputReceived);
+        }
+        if self.permissions_denied {
+            event_types.push(EventType::Key);
+        }
+        event_types
+    }
+
+    /// Poll again after an exponentially growing delay, a bounded number of times.
+    fn retry_list_clients(&mut self) {
+        if self.list_clients_retries >= MAX_LIST_CLIENTS_RETRIES {
+            log!(
+                self,
+                Warn,
+                "No usable current client; giving up until the next event."
+            );
+            return;
+        }
+        let delay = self.reaction_seconds * 2f64.powi(self.list_clients_retries as i32);
+        self.list_clients_retries += 1;
+        log!(
+            self,
+            Debug,
+            "No usable current client; retry {} in {}s.",
+            self.list_clients_retries,
+            delay
+        );
+        self.schedule_timer(delay);
+    }
+
+    /// Every request counts as unanswered until a `ListClients` event arrives. Only one request is
+    /// outstanding at a time; whatever asks for another meanwhile (e.g., a burst of `PaneUpdate`s
+    /// while resizing) gets a single one more on the next tick.
+    fn list_clients(&mut self) {
+        if !self.is_enabled {
+            return;
+        }
+        if self.list_clients_in_flight.is_some() {
+            if !self.list_clients_deferred {
+                log!(
+                    self,
+                    Trace,
+                    "A `list_clients` request is in flight; deferring."
+                );
+            }
+            self.list_clients_deferred = true;
+            self.start_timer();
+            return;
+        }
+        if let Some(wait_seconds) = self.poll_budget_wait_seconds() {
+            if !self.list_clients_deferred {
+                log!(
+                    self,
+                    Info,
+                    "Polling budget of {}/s used up; deferring `list_clients` by {:.2}s.",
+                    self.max_polls_per_second,
+                    wait_seconds
+                );
+            }
+            self.list_clients_deferred = true;
+            self.schedule_timer(wait_seconds);
+            return;
+        }
+        self.recent_polls.push_back(self.clock_seconds);
+        self.list_clients_in_flight = Some(0);
+        self.metrics.list_clients_calls += 1;
+        self.unanswered_list_clients += 1;
+        self.actions.push(Action::ListClients);
+    }
+
+    /// How long until the polling budget allows another request, if it does not now.
+    fn poll_budget_wait_seconds(&mut self) -> Option<f64> {
+        if self.max_polls_per_second == 0 {
+            return None;
+        }
+        while self
+            .recent_polls
+            .front()
+            .is_some_and(|poll_seconds| *poll_seconds <= self.clock_seconds - 1.0)
+        {
+            self.recent_polls.pop_front();
+        }
+        if self.recent_polls.len() < self.max_polls_per_second {
+            return None;
+        }
+        self.recent_polls
+            .front()
+            .map(|poll_seconds| poll_seconds + 1.0 - self.clock_seconds)
+    }
+
+    fn hide_self(&mut self) {
+        if !self.configuration_errors.is_empty() && cfg!(feature = "ui") {
+            // Leave the errors on screen.
+            return;
+        }
+        self.is_hiding_self = true;
+        self.actions.push(Action::HideSelf);
+    }
+
+    fn show_self(&mut self) {
+        self.is_self_hidden = false;
+        self.actions.push(Action::ShowSelf);
+    }
+
+    /// Poll for the command in the focused pane, unless events will tell us about it anyway.
+    /// Polling starts out fast and backs off while nothing changes.
+    fn start_polling(&mut self) {
+        self.poll_seconds = self.reaction_seconds;
+        if self.is_focused_pane_event_driven() {
+            return;
+        }
+        if self.is_focused_pane_slow_changing() {
+            self.poll_seconds = self.max_poll_seconds;
+            self.schedule_timer(self.poll_seconds);
+        } else {
+            self.start_timer();
+        }
+    }
+
+    /// Tabs and panes of the new session trickle in after a switch (e.g., with zellij-switch or
+    /// the session manager), and deciding on any of them would flip modes for nothing.
+    fn start_session_settling(&mut self) {
+        let until = self.clock_seconds + SESSION_SETTLE_SECONDS;
+        self.session_settling = Some((self.clock_seconds, until));
+        self.schedule_timer(SESSION_SETTLE_SECONDS);
+    }
+
+    fn extend_session_settling(&mut self) {
+        if let Some((started, until)) = self.session_settling.as_mut() {
+            *until = (self.clock_seconds + SESSION_SETTLE_SECONDS)
+                .min(*started + MAX_SESSION_SETTLE_SECONDS)
+                .max(*until);
+        }
+    }
+
+    /// Whether decisions still wait for the new session; if so, one is scheduled for when they
+    /// no longer do.
+    fn is_session_settling(&mut self) -> bool {
+        match self.session_settling {
+            Some((_, until)) if self.clock_seconds < until => {
+                log!(self, Trace, "Waiting for the new session to settle.");
+                self.schedule_timer(until - self.clock_seconds);
+                true
+            }
+            Some(_) => {
+                log!(self, Debug, "New session settled.");
+                self.session_settling = None;
+                false
+            }
+            None => false,
+        }
+    }
+
+    fn start_timer(&mut self) {
+        self.schedule_timer(self.reaction_seconds);
+    }
+
+    /// Timers cannot be cancelled, so one is only worth scheduling if it fires before the one
+    /// already pending.
+    fn schedule_timer(&mut self, seconds: f64) {
+        let deadline_seconds = self.clock_seconds + seconds;
+        let fires_sooner = self
+            .timer_deadline_seconds
+            .is_none_or(|timer_deadline_seconds| deadline_seconds < timer_deadline_seconds);
+        if self.is_enabled && self.is_visible && fires_sooner {
+            self.actions.push(Action::SetTimeout(seconds));
+            self.timer_deadline_seconds = Some(deadline_seconds);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use zellij_tile::prelude::{KeyWithModifier, ModeInfo, SessionInfo, TabInfo};
+
+    fn pane(id: u32, is_focused: bool) -> PaneInfo {
+        PaneInfo {
+            id,
+            is_focused,
+            is_selectable: true,
+            title: "zsh".to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn tab(position: usize, active: bool) -> TabInfo {
+        TabInfo {
+            position,
+            active,
+            ..Default::default()
+        }
+    }
+
+    fn manifest(tabs: Vec<Vec<PaneInfo>>) -> PaneManifest {
+        PaneManifest {
+            panes: tabs.into_iter().enumerate().collect(),
+        }
+    }
+
+    fn running(pane_id: u32, running_command: &str) -> Event {
+        Event::ListClients(vec![ClientInfo::new(
+            1,
+            PaneId::Terminal(pane_id),
+            running_command.to_string(),
+            true,
+        )])
+    }
+
+    fn payload(payload: &str) -> PipeMessage {
+        PipeMessage::new(
+            PipeSource::Keybind,
+            "autolock",
+            &Some(payload.to_string()),
+            &None,
+            false,
+        )
+    }
+
+    fn mode(mode: InputMode) -> Event {
+        Event::ModeUpdate(ModeInfo {
+            mode,
+            ..Default::default()
+        })
+    }
+
+    /// An engine that has its permissions and knows about one tab with panes 1 (focused) and 2.
+    fn initialized_engine() -> Engine {
+        configured_engine(&[])
+    }
+
+    /// Like [`initialized_engine`], but loaded with these settings instead of none at all.
+    fn configured_engine(configuration: &[(&str, &str)]) -> Engine {
+        let mut engine = Engine::default();
+        engine.load(
+            configuration
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+        );
+        engine.update(Event::PermissionRequestResult(PermissionStatus::Granted));
+        engine.update(Event::TabUpdate(vec![tab(0, true)]));
+        engine.update(Event::PaneUpdate(manifest(vec![vec![
+            pane(1, true),
+            pane(2, false),
+        ]])));
+        engine.update(running(1, "zsh"));
+        engine.take_actions();
+        engine
+    }
+
+    fn mode_switches(engine: &mut Engine) -> Vec<InputMode> {
+        engine
+            .take_actions()
+            .into_iter()
+            .filter_map(|action| match action {
+                Action::SwitchToInputMode(input_mode) => Some(input_mode),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn load_subscribes_without_touching_modes() {
+        let mut engine = Engine::default();
+        engine.load(BTreeMap::new());
+        let actions = engine.take_actions();
+        assert!(matches!(actions[0], Action::RequestPermissions(_)));
+        assert!(matches!(&actions[1..], [Action::Subscribe(_)]));
+    }
+
+    #[test]
+    fn works_as_a_bare_alias() {
+        let mut engine = initialized_engine();
+        engine.update(running(1, "fzf"));
+        assert_eq!(mode_switches(&mut engine), vec![InputMode::Locked]);
+        engine.update(mode(InputMode::Locked));
+        engine.pipe(PipeMessage::new(
+            PipeSource::Cli("autolock".to_string()),
+            "autolock:disable",
+            &None,
+            &None,
+            false,
+        ));
+        assert!(!engine.is_enabled);
+    }
+
+    #[test]
+    fn has_the_same_default_triggers_when_bare() {
+        let mut engine = Engine::default();
+        engine.load(BTreeMap::from([(
+            "reaction_seconds".to_string(),
+            "0.3".to_string(),
+        )]));
+        assert_eq!(engine.lock_trigger_cmds, DEFAULT_TRIGGERS);
+        let mut bare_engine = Engine::default();
+        bare_engine.load(BTreeMap::new());
+        assert!(bare_engine
+            .lock_trigger_cmds
+            .starts_with(&engine.lock_trigger_cmds));
+    }
+
+    #[test]
+    fn subscribes_to_input_only_while_enabled() {
+        let mut engine = initialized_engine();
+        engine.pipe(payload("disable"));
+        assert!(engine
+            .take_actions()
+            .contains(&Action::Unsubscribe(vec![EventType::InputReceived])));
+        engine.pipe(payload("enable"));
+        assert!(engine
+            .take_actions()
+            .contains(&Action::Subscribe(vec![EventType::InputReceived])));
+    }
+
+    #[test]
+    fn asks_again_for_denied_permissions() {
+        let mut engine = Engine::default();
+        engine.load(BTreeMap::new());
+        engine.take_actions();
+        engine.update(Event::PermissionRequestResult(PermissionStatus::Denied));
+        assert!(engine.permissions_denied());
+        assert!(engine
+            .take_actions()
+            .contains(&Action::Subscribe(vec![EventType::Key])));
+
+        engine.update(Event::Key(KeyWithModifier::new(BareKey::Char('x'))));
+        assert!(engine.take_actions().is_empty());
+        engine.update(Event::Key(KeyWithModifier::new(BareKey::Char('r'))));
+        assert!(matches!(
+            engine.take_actions()[0],
+            Action::RequestPermissions(_)
+        ));
+        engine.update(Event::PermissionRequestResult(PermissionStatus::Granted));
+        assert!(!engine.permissions_denied());
+        assert!(engine
+            .take_actions()
+            .contains(&Action::Unsubscribe(vec![EventType::Key])));
+    }
+
+    #[test]
+    fn waits_for_initialization() {
+        let mut engine = Engine::default();
+        engine.load(BTreeMap::new());
+        engine.update(Event::PermissionRequestResult(PermissionStatus::Granted));
+        engine.update(running(1, "vim"));
+        assert!(mode_switches(&mut engine).is_empty());
+    }
+
+    #[test]
+    fn finds_the_focused_tab_without_a_tab_update() {
+        let mut engine = Engine::default();
+        engine.load(BTreeMap::new());
+        engine.update(Event::PermissionRequestResult(PermissionStatus::Granted));
+        engine.update(Event::PaneUpdate(manifest(vec![vec![
+            pane(1, true),
+            pane(2, false),
+        ]])));
+        assert_eq!(engine.focused_tab_position(), 0);
+        engine.update(running(1, "vim"));
+        assert_eq!(mode_switches(&mut engine), vec![InputMode::Locked]);
+
+        // With several tabs, the one holding the current client's pane.
+        let mut engine = Engine::default();
+        engine.load(BTreeMap::new());
+        engine.update(Event::PermissionRequestResult(PermissionStatus::Granted));
+        engine.update(running(3, "zsh"));
+        engine.update(Event::PaneUpdate(manifest(vec![
+            vec![pane(1, true), pane(2, false)],
+            vec![pane(3, true)],
+        ])));
+        assert_eq!(engine.focused_tab_position(), 1);
+        engine.update(running(3, "vim"));
+        assert_eq!(mode_switches(&mut engine), vec![InputMode::Locked]);
+    }
+
+    #[test]
+    fn locks_when_trigger_starts() {
+        let mut engine = initialized_engine();
+        engine.update(running(1, "vim main.rs"));
+        assert_eq!(mode_switches(&mut engine), vec![InputMode::Locked]);
+    }
+
+    #[test]
+    fn falls_back_to_the_pane_manifest_for_unknown_commands() {
+        let mut engine = initialized_engine();
+        engine.update(Event::PaneUpdate(manifest(vec![vec![
+            pane(1, false),
+            PaneInfo {
+                terminal_command: Some("nvim main.rs".to_string()),
+                ..pane(2, true)
+            },
+        ]])));
+        engine.take_actions();
+        engine.update(running(2, "N/A"));
+        assert_eq!(mode_switches(&mut engine), vec![InputMode::Locked]);
+        assert_eq!(engine.detected_commands[&2], "nvim main.rs");
+    }
+
+    #[test]
+    fn locks_panes_opened_by_zellij_edit() {
+        let mut engine = initialized_engine();
+        engine.update(Event::PaneUpdate(manifest(vec![vec![
+            pane(1, false),
+            titled_pane(2, true, "hx notes.txt"),
+        ]])));
+        engine.take_actions();
+        engine.update(Event::EditPaneOpened(2, BTreeMap::new()));
+        assert_eq!(mode_switches(&mut engine), vec![InputMode::Locked]);
+        engine.update(mode(InputMode::Locked));
+        engine.update(Event::EditPaneExited(2, Some(0), BTreeMap::new()));
+        assert_eq!(mode_switches(&mut engine), vec![InputMode::Normal]);
+    }
+
+    #[test]
+    fn unlocks_when_trigger_exits() {
+        let mut engine = initialized_engine();
+        engine.update(running(1, "vim"));
+        engine.update(mode(InputMode::Locked));
+        engine.take_actions();
+        engine.update(running(1, "zsh"));
+        assert_eq!(mode_switches(&mut engine), vec![InputMode::Normal]);
+    }
+
+    #[test]
+    fn settles_on_one_switch_per_batch() {
+        let mut engine = initialized_engine();
+        engine.apply_trigger(1, "vim".to_string(), true);
+        engine.apply_trigger(2, "zsh".to_string(), false);
+        assert!(mode_switches(&mut engine).is_empty());
+        assert_eq!(engine.expected_mode, None);
+
+        engine.apply_trigger(1, "vim".to_string(), true);
+        engine.apply_trigger(2, "zsh".to_string(), false);
+        engine.apply_trigger(1, "vim".to_string(), true);
+        assert_eq!(mode_switches(&mut engine), vec![InputMode::Locked]);
+    }
+
+    #[test]
+    fn counts_what_it_does() {
+        let mut engine = initialized_engine();
+        engine.update(mode(InputMode::Pane));
+        engine.update(running(1, "vim"));
+        engine.update(mode(InputMode::Normal));
+        engine.update(running(1, "nvim"));
+        engine.take_actions();
+        let status = engine.format_status();
+        assert!(status.contains("mode_switches: 1\n"));
+        assert!(status.contains("suppressed_switches: 1\n"));
+        assert!(status.contains("matched_triggers: 2"));
+    }
+
+    #[test]
+    fn tells_who_locked() {
+        let mut engine = initialized_engine();
+        assert!(engine.format_status().contains("locked_by: none\n"));
+        engine.update(running(1, "vim"));
+        engine.update(mode(InputMode::Locked));
+        assert!(engine.format_status().contains("locked_by: autolock\n"));
+
+        engine.update(mode(InputMode::Normal));
+        assert!(engine.format_status().contains("locked_by: none\n"));
+        engine.update(mode(InputMode::Locked));
+        assert!(engine.format_status().contains("locked_by: user\n"));
+    }
+
+    #[test]
+    fn measures_switch_latency() {
+        let mut engine = configured_engine(&[("measure_latency", "true")]);
+        engine.update(Event::InputReceived);
+        engine.set_clock(0.3, 0.0);
+        engine.update(Event::Timer(0.3));
+        engine.set_clock(0.32, 0.0);
+        engine.update(running(1, "vim"));
+        assert_eq!(mode_switches(&mut engine), [InputMode::Locked]);
+        let latency = engine.latency.as_ref().unwrap().format();
+        assert!(latency.starts_with("samples: 1\n"));
+        assert!(latency.contains("\ntimer_to_detection\t20\t20\t20\n"));
+        assert!(latency.ends_with("\ntotal\t320\t320\t320"));
+    }
+
+    #[test]
+    fn keeps_usage_stats() {
+        let mut engine = initialized_engine();
+        engine.update(mode(InputMode::Normal));
+        engine.set_clock(10.0, 0.0);
+        engine.update(running(1, "vim"));
+        engine.take_actions();
+        engine.update(mode(InputMode::Locked));
+        engine.set_clock(40.0, 0.0);
+        engine.update(running(1, "zsh"));
+        engine.take_actions();
+        engine.update(mode(InputMode::Normal));
+        engine.update(running(1, "fzf"));
+        engine.take_actions();
+        engine.update(mode(InputMode::Locked));
+        engine.set_clock(50.0, 0.0);
+        assert_eq!(
+            engine.format_stats(),
+            "locked_seconds: 40\nunlocked_seconds: 10\nlocked_share: 80%\nautomatic_switches: 3\ntop_triggers: fzf 1, vim 1"
+        );
+    }
+
+    #[test]
+    fn remembers_being_disabled() {
+        let dir = TempDir::new("enabled");
+        let path = dir.file("autolock-state");
+        let persisted_engine = || Engine {
+            persist_file: Some(path.clone()),
+            ..Engine::default()
+        };
+        let mut engine = persisted_engine();
+        engine.load(BTreeMap::new());
+        engine.pipe(payload("disable"));
+
+        let mut engine = persisted_engine();
+        engine.load(BTreeMap::from([(
+            "is_enabled".to_string(),
+            "true".to_string(),
+        )]));
+        assert!(!engine.is_enabled);
+    }
+
+    #[test]
+    fn remembers_triggers_changed_at_runtime() {
+        let dir = TempDir::new("triggers");
+        let path = dir.file("autolock-state");
+        let persisted_engine = || Engine {
+            persist_file: Some(path.clone()),
+            ..Engine::default()
+        };
+        let mut engine = persisted_engine();
+        engine.load(BTreeMap::new());
+        engine.pipe(payload("add-trigger lazygit"));
+        engine.pipe(payload("remove-trigger git"));
+
+        let mut engine = persisted_engine();
+        engine.load(BTreeMap::from([(
+            "triggers".to_string(),
+            "vim|git".to_string(),
+        )]));
+        assert_eq!(engine.lock_trigger_cmds, ["vim", "lazygit"]);
+        assert!(engine.is_enabled);
+        assert!(engine.persisted.is_enabled.is_none());
+    }
+
+    #[test]
+    fn keeps_defaults_for_bad_settings() {
+        let mut engine = Engine::default();
+        engine.load(BTreeMap::from([
+            ("reaction_seconds".to_string(), "fast".to_string()),
+            ("max_poll_seconds".to_string(), "-1".to_string()),
+            ("max_polls_per_second".to_string(), "4".to_string()),
+            ("log_file_keep".to_string(), "5".to_string()),
+        ]));
+        assert_eq!(engine.reaction_seconds, 0.3);
+        assert_eq!(engine.max_poll_seconds, 10.0);
+        assert_eq!(engine.max_polls_per_second, 4);
+        assert_eq!(engine.log_file_keep, 5);
+        assert_eq!(
+            engine.configuration_errors(),
+            [
+                "`reaction_seconds` must be a finite, non-negative number, not `fast`; using the default.",
+                "`max_poll_seconds` must be a finite, non-negative number, not `-1`; using the default.",
+            ]
+        );
+        assert_eq!(
+            engine.take_actions().contains(&Action::ShowSelf),
+            cfg!(feature = "ui")
+        );
+    }
+
+    #[test]
+    fn rejects_infinite_settings() {
+        let mut engine = Engine::default();
+        engine.load(BTreeMap::from([
+            ("reaction_seconds".to_string(), "inf".to_string()),
+            ("max_poll_seconds".to_string(), "NaN".to_string()),
+        ]));
+        assert_eq!(engine.reaction_seconds, 0.3);
+        assert_eq!(engine.max_poll_seconds, 10.0);
+        assert_eq!(
+            engine.configuration_errors(),
+            [
+                "`reaction_seconds` must be a finite, non-negative number, not `inf`; using the default.",
+                "`max_poll_seconds` must be a finite, non-negative number, not `NaN`; using the default.",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_unknown_log_settings() {
+        let mut engine = Engine::default();
+        engine.load(BTreeMap::from([
+            ("log_level".to_string(), "warning".to_string()),
+            ("log_format".to_string(), "yaml".to_string()),
+        ]));
+        assert_eq!(engine.log_level, LogLevel::Off);
+        assert_eq!(
+            engine.configuration_errors(),
+            [
+                "`log_format` must be `text` or `json`, not `yaml`; using the default.",
+                "`log_level` must be off, error, warn, info, debug, or trace, not `warning`; using the default.",
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_what_it_needs() {
+        let mut engine = initialized_engine();
+        engine.update(mode(InputMode::Normal));
+        let doctor = engine.format_doctor();
+        assert!(doctor.starts_with("ok\tpermissions granted\n"));
+        assert!(doctor.contains("ok\tevent TabUpdate seen 0.0s ago\n"));
+        assert!(doctor.contains("--\tevent InputReceived never seen\n"));
+        assert!(doctor.contains("ok\tlist_clients named the focused pane 0.0s ago\n"));
+        assert!(doctor.ends_with("ok\t10 triggers"));
+
+        engine.set_triggers(vec![
+            "vim".to_string(),
+            "zsh".to_string(),
+            "vim".to_string(),
+        ]);
+        assert!(engine.format_doctor().ends_with(
+            "FAIL\ttriggers: `zsh` is a shell, so it runs in most panes; `vim` is listed twice"
+        ));
+        let doctor = Engine::default().format_doctor();
+        assert!(doctor.starts_with("FAIL\tpermissions not answered yet\n"));
+        assert!(doctor.contains("FAIL\tlist_clients never answered\n"));
+    }
+
+    #[test]
+    fn traces_recent_events_and_decisions() {
+        let mut engine = initialized_engine();
+        engine.update(running(1, "vim"));
+        engine.take_actions();
+        let trace = engine.format_trace();
+        assert!(trace.ends_with(
+            "0.000\tevent ListClients\n0.000\tpane 1 runs `vim` (trigger)\n0.000\tswitch to Locked"
+        ));
+
+        for _ in 0..TRACE_CAPACITY {
+            engine.update(Event::InputReceived);
+        }
+        assert_eq!(engine.format_trace().lines().count(), TRACE_CAPACITY);
+        assert!(!engine.format_trace().contains("switch"));
+    }
+
+    #[test]
+    fn switches_once_until_acknowledged() {
+        let mut engine = initialized_engine();
+        engine.update(running(1, "vim"));
+        engine.update(running(1, "vim"));
+        assert_eq!(mode_switches(&mut engine), vec![InputMode::Locked]);
+    }
+
+    #[test]
+    fn takes_its_own_switches_for_what_they_are() {
+        let mut engine = initialized_engine();
+        engine.update(running(1, "vim"));
+        assert_eq!(mode_switches(&mut engine), vec![InputMode::Locked]);
+        engine.update(mode(InputMode::Locked));
+        assert!(engine.take_actions().is_empty());
+        assert!(engine.latest_assessment.is_some());
+
+        // A switch by the user is looked into.
+        engine.update(mode(InputMode::Normal));
+        assert_eq!(engine.latest_assessment, None);
+    }
+
+    #[test]
+    fn switches_again_when_unconfirmed() {
+        let mut engine = initialized_engine();
+        engine.update(running(1, "vim"));
+        assert_eq!(mode_switches(&mut engine), [InputMode::Locked]);
+        engine.set_clock(1.0, 0.0);
+        engine.update(Event::Timer(1.0));
+        engine.update(running(1, "vim"));
+        assert!(mode_switches(&mut engine).is_empty());
+
+        // No ModeUpdate ever came.
+        engine.set_clock(2.5, 0.0);
+        engine.update(Event::Timer(1.5));
+        engine.update(running(1, "vim"));
+        assert_eq!(mode_switches(&mut engine), [InputMode::Locked]);
+    }
+
+    #[test]
+    fn checks_for_confirmation_after_a_sooner_timer() {
+        let mut engine = initialized_engine();
+        engine.update(Event::InputReceived);
+        engine.take_actions();
+        engine.pipe(PipeMessage::new(
+            PipeSource::Cli("pipe-id".to_string()),
+            "autolock",
+            &Some("cmd-start vim".to_string()),
+            &Some(BTreeMap::from([("pane_id".to_string(), "1".to_string())])),
+            false,
+        ));
+        // The pending tick comes before the confirmation is due.
+        let actions = engine.take_actions();
+        assert!(actions.contains(&Action::SwitchToInputMode(InputMode::Locked)));
+        assert!(!actions
+            .iter()
+            .any(|action| matches!(action, Action::SetTimeout(_))));
+
+        engine.set_clock(0.5, 0.0);
+        engine.update(Event::Timer(0.3));
+        assert!(engine.take_actions().contains(&Action::SetTimeout(1.5)));
+
+        // No ModeUpdate ever came.
+        engine.set_clock(2.0, 0.0);
+        engine.update(Event::Timer(1.5));
+        assert_eq!(mode_switches(&mut engine), [InputMode::Locked]);
+    }
+
+    #[test]
+    fn skips_unchanged_polls() {
+        let mut engine = initialized_engine();
+        engine.update(running(1, "vim"));
+        engine.update(mode(InputMode::Locked));
+        engine.take_actions();
+        // The user unlocks while the trigger keeps running; only then is it worth locking again.
+        engine.update(running(1, "vim"));
+        assert!(mode_switches(&mut engine).is_empty());
+        engine.update(mode(InputMode::Normal));
+        engine.update(running(1, "vim"));
+        assert_eq!(mode_switches(&mut engine), vec![InputMode::Locked]);
+    }
+
+    #[test]
+    fn reassesses_on_focus_change() {
+        let mut engine = initialized_engine();
+        engine.update(running(1, "vim"));
+        engine.update(mode(InputMode::Locked));
+        engine.take_actions();
+
+        engine.update(Event::PaneUpdate(manifest(vec![vec![
+            pane(1, false),
+            pane(2, true),
+        ]])));
+        assert!(engine.take_actions().contains(&Action::ListClients));
+
+        engine.update(running(2, "zsh"));
+        assert_eq!(mode_switches(&mut engine), vec![InputMode::Normal]);
+    }
+
+    fn titled_pane(id: u32, is_focused: bool, title: &str) -> PaneInfo {
+        PaneInfo {
+            title: title.to_string(),
+            ..pane(id, is_focused)
+        }
+    }
+
+    #[test]
+    fn reassesses_after_swapping_layouts() {
+        let mut engine = initialized_engine();
+        engine.update(Event::TabUpdate(vec![TabInfo {
+            active_swap_layout_name: Some("vertical".to_string()),
+            ..tab(0, true)
+        }]));
+        assert!(engine.take_actions().contains(&Action::ListClients));
+        engine.update(running(1, "zsh"));
+
+        // Panes rearranged without a new swap layout name, e.g., `next-swap-layout` wrapping.
+        engine.update(Event::PaneUpdate(manifest(vec![vec![
+            pane(1, true),
+            PaneInfo {
+                pane_x: 40,
+                ..pane(2, false)
+            },
+        ]])));
+        assert!(engine.take_actions().contains(&Action::ListClients));
+    }
+
+    #[test]
+    fn reassesses_on_fullscreen_toggles() {
+        let mut engine = initialized_engine();
+        engine.update(Event::PaneUpdate(manifest(vec![vec![
+            PaneInfo {
+                is_fullscreen: true,
+                ..pane(1, true)
+            },
+            pane(2, false),
+        ]])));
+        assert!(engine.latest_tab_pane.pane_is_fullscreen);
+        assert!(engine.take_actions().contains(&Action::ListClients));
+        engine.update(running(1, "zsh"));
+
+        engine.update(Event::PaneUpdate(manifest(vec![vec![
+            pane(1, true),
+            pane(2, false),
+        ]])));
+        assert!(!engine.latest_tab_pane.pane_is_fullscreen);
+        assert!(engine.take_actions().contains(&Action::ListClients));
+    }
+
+    #[test]
+    fn polls_after_input() {
+        let mut engine = initialized_engine();
+        engine.update(Event::Timer(0.3));
+        engine.update(running(1, "zsh"));
+        engine.take_actions();
+        engine.update(Event::InputReceived);
+        assert_eq!(engine.take_actions(), vec![Action::SetTimeout(0.3)]);
+    }
+
+    #[test]
+    fn waits_for_a_pause_in_typing() {
+        let mut engine = initialized_engine();
+        engine.update(Event::Timer(0.3));
+        engine.update(running(1, "zsh"));
+        engine.update(Event::InputReceived);
+        engine.update(Event::InputReceived);
+        engine.take_actions();
+        engine.update(Event::Timer(0.3));
+        assert_eq!(engine.take_actions(), vec![Action::SetTimeout(0.3)]);
+        engine.update(Event::Timer(0.3));
+        assert!(engine.take_actions().contains(&Action::ListClients));
+    }
+
+    #[test]
+    fn assesses_while_typing_without_pause() {
+        let mut engine = initialized_engine();
+        engine.update(Event::InputReceived);
+        let mut ticks = 0;
+        loop {
+            engine.update(Event::InputReceived);
+            engine.take_actions();
+            engine.update(Event::Timer(0.3));
+            ticks += 1;
+            if engine.take_actions().contains(&Action::ListClients) {
+                break;
+            }
+        }
+        assert_eq!(ticks, MAX_TYPING_DEFERRALS + 1);
+    }
+
+    #[test]
+    fn backs_off_while_nothing_changes() {
+        let mut engine = initialized_engine();
+        let mut delays = Vec::new();
+        for _ in 0..8 {
+            engine.update(Event::Timer(0.0));
+            engine.update(running(1, "zsh"));
+            for action in engine.take_actions() {
+                if let Action::SetTimeout(seconds) = action {
+                    delays.push(seconds);
+                }
+            }
+        }
+        assert_eq!(delays, vec![0.6, 1.2, 2.4, 4.8, 9.6, 10.0, 10.0, 10.0]);
+
+        engine.update(Event::InputReceived);
+        assert_eq!(engine.take_actions(), vec![Action::SetTimeout(0.3)]);
+    }
+
+    #[test]
+    fn pauses_only_while_hidden() {
+        let mut engine = initialized_engine();
+        engine.update(Event::Timer(0.3));
+        engine.take_actions();
+        engine.update(Event::Visible(false));
+        engine.update(Event::InputReceived);
+        assert_eq!(engine.take_actions(), vec![Action::SetTimeout(0.3)]);
+
+        // Once hidden, no longer being visible means the client detached.
+        engine.update(Event::Visible(false));
+        engine.update(Event::Timer(0.3));
+        engine.update(Event::InputReceived);
+        assert!(engine.take_actions().is_empty());
+        engine.update(Event::Visible(true));
+        assert!(engine.take_actions().contains(&Action::SetTimeout(0.3)));
+
+        let mut engine = configured_engine(&[("reaction_seconds", "fast")]);
+        engine.update(Event::Visible(false));
+        engine.update(running(1, "vim"));
+        assert_eq!(mode_switches(&mut engine), vec![InputMode::Locked]);
+    }
+
+    #[test]
+    fn slows_polling_panes_whose_titles_follow_commands() {
+        let mut engine = initialized_engine();
+        engine.update(Event::PaneUpdate(manifest(vec![vec![
+            titled_pane(1, true, "vim main.rs"),
+            pane(2, false),
+        ]])));
+        assert!(engine.take_actions().contains(&Action::ListClients));
+        engine.update(running(1, "vim main.rs"));
+        engine.update(mode(InputMode::Locked));
+        engine.update(Event::Timer(0.3));
+        engine.update(running(1, "vim main.rs"));
+        assert!(engine.take_actions().contains(&Action::SetTimeout(10.0)));
+
+        engine.update(Event::InputReceived);
+        assert!(engine.take_actions().is_empty());
+
+        // A title that stops following the command is caught by the slow poll.
+        engine.update(Event::Timer(10.0));
+        engine.update(Event::Timer(0.3));
+        assert!(engine.take_actions().contains(&Action::ListClients));
+        engine.update(running(1, "less notes.txt"));
+        assert_eq!(mode_switches(&mut engine), vec![InputMode::Normal]);
+        engine.update(mode(InputMode::Normal));
+        engine.update(running(1, "vim main.rs"));
+        engine.update(mode(InputMode::Locked));
+        engine.take_actions();
+
+        engine.update(Event::PaneUpdate(manifest(vec![vec![
+            titled_pane(1, true, "zsh"),
+            pane(2, false),
+        ]])));
+        assert!(engine.take_actions().contains(&Action::ListClients));
+        engine.update(running(1, "zsh"));
+        assert_eq!(mode_switches(&mut engine), vec![InputMode::Normal]);
+    }
+
+    #[test]
+    fn slows_polling_panes_rooted_in_a_trigger() {
+        let mut engine = initialized_engine();
+        engine.update(Event::PaneUpdate(manifest(vec![vec![
+            pane(1, false),
+            pane(2, true),
+        ]])));
+        engine.update(running(2, "nvim"));
+        engine.update(mode(InputMode::Locked));
+        engine.update(Event::Timer(0.3));
+        engine.update(running(2, "nvim"));
+        assert!(engine.take_actions().contains(&Action::SetTimeout(10.0)));
+
+        // The trigger only ends along with its pane.
+        engine.update(Event::PaneClosed(PaneId::Terminal(2)));
+        assert!(engine.take_actions().contains(&Action::ListClients));
+
+        // Pane 1 ran a shell first, so it gets polled as usual.
+        engine.update(running(1, "vim"));
+        engine.update(mode(InputMode::Locked));
+        engine.update(Event::Timer(0.3));
+        engine.update(running(1, "vim"));
+        assert!(engine.take_actions().contains(&Action::SetTimeout(0.3)));
+    }
+
+    #[test]
+    fn subscribes_again_when_requests_go_unanswered() {
+        let mut engine = initialized_engine();
+        // Each request is presumed lost a tick later, and the next tick sends another.
+        for tick in 1..=5 {
+            engine.set_clock(tick as f64, 0.0);
+            engine.update(Event::Timer(1.0));
+        }
+        assert_eq!(engine.unanswered_list_clients, MAX_UNANSWERED_LIST_CLIENTS);
+        engine.take_actions();
+        engine.set_clock(6.0, 0.0);
+        engine.update(Event::Timer(1.0));
+        let actions = engine.take_actions();
+        assert!(matches!(actions[0], Action::RequestPermissions(_)));
+        assert!(matches!(actions[1], Action::Subscribe(_)));
+        assert_eq!(engine.unanswered_list_clients, 0);
+    }
+
+    #[test]
+    fn retries_when_no_client_is_current() {
+        let mut engine = initialized_engine();
+        engine.update(Event::Timer(0.3));
+        assert!(engine.take_actions().contains(&Action::ListClients));
+
+        // Right after attaching, there may be no current client, or no command for it yet.
+        engine.update(Event::ListClients(vec![]));
+        assert_eq!(engine.take_actions(), [Action::SetTimeout(0.3)]);
+        engine.update(Event::Timer(0.3));
+        assert!(engine.take_actions().contains(&Action::ListClients));
+        engine.update(running(1, ""));
+        assert_eq!(engine.take_actions(), [Action::SetTimeout(0.6)]);
+        engine.update(Event::Timer(0.6));
+        engine.take_actions();
+        engine.update(running(1, "vim"));
+        assert_eq!(mode_switches(&mut engine), vec![InputMode::Locked]);
+        assert_eq!(engine.list_clients_retries, 0);
+    }
+
+    #[test]
+    fn coalesces_list_clients_during_bursts() {
+        let mut engine = initialized_engine();
+        for is_first_focused in [false, true, false] {
+            engine.update(Event::PaneUpdate(manifest(vec![vec![
+                pane(1, is_first_focused),
+                pane(2, !is_first_focused),
+            ]])));
+        }
+        let list_clients = |actions: Vec<Action>| {
+            actions
+                .iter()
+                .filter(|action| **action == Action::ListClients)
+                .count()
+        };
+        assert_eq!(list_clients(engine.take_actions()), 1);
+
+        // The next tick makes a single request for whatever changed in the meantime.
+        engine.update(running(1, "zsh"));
+        engine.update(Event::Timer(0.3));
+        assert_eq!(list_clients(engine.take_actions()), 1);
+    }
+
+    #[test]
+    fn keeps_one_request_in_flight() {
+        let mut engine = initialized_engine();
+        engine.update(Event::PaneUpdate(manifest(vec![vec![
+            pane(1, false),
+            pane(2, true),
+        ]])));
+        assert!(engine.take_actions().contains(&Action::ListClients));
+        engine.update(Event::Timer(0.3));
+        assert!(!engine.take_actions().contains(&Action::ListClients));
+        // Unanswered for a whole tick, so it was probably lost.
+        engine.update(Event::Timer(0.3));
+        assert!(engine.take_actions().contains(&Action::ListClients));
+    }
+
+    #[test]
+    fn keeps_to_the_polling_budget() {
+        let mut engine = configured_engine(&[("max_polls_per_second", "2")]);
+        let mut polls = 0;
+        for tick in 0..10 {
+            engine.set_clock(1.0 + tick as f64 * 0.1, 0.0);
+            engine.update(Event::Timer(0.1));
+            polls += engine
+                .take_actions()
+                .iter()
+                .filter(|action| **action == Action::ListClients)
+                .count();
+            engine.update(running(1, "zsh"));
+        }
+        assert_eq!(polls, 2);
+
+        engine.set_clock(2.0, 0.0);
+        engine.update(Event::Timer(0.1));
+        assert!(engine.take_actions().contains(&Action::ListClients));
+    }
+
+    #[test]
+    fn logs_to_a_file() {
+        let dir = TempDir::new("log");
+        let path = dir.file("autolock.log");
+        let mut engine = Engine::default();
+        engine.load(BTreeMap::from([
+            ("log_level".to_string(), "info".to_string()),
+            ("log_file".to_string(), path.clone()),
+        ]));
+        let log = std::fs::read_to_string(&path).unwrap();
+        assert!(log.contains(
+            "[autolock] 1970-01-01T00:00:00.000Z client=- tab=- pane=- INFO Configuration loaded."
+        ));
+    }
+
+    #[test]
+    fn logs_one_line_per_mode_switch() {
+        let dir = TempDir::new("audit");
+        let path = dir.file("autolock.log");
+        let mut engine = initialized_engine();
+        engine.log_level = LogLevel::Info;
+        engine.open_log_file(&path);
+        engine.update(running(1, "vim"));
+        engine.update(running(1, "vim"));
+        engine.take_actions();
+        engine.update(running(1, "zsh"));
+        engine.update(running(1, "vim"));
+        engine.take_actions();
+        let log = std::fs::read_to_string(&path).unwrap();
+        let switches: Vec<&str> = log
+            .lines()
+            .filter(|line| line.contains("Switched"))
+            .collect();
+        assert_eq!(switches.len(), 1);
+        assert!(switches[0].ends_with(
+            "INFO Switched from Normal to Locked mode: pane 1 runs `vim` (trigger `vim`), seen on ListClients."
+        ));
+    }
+
+    #[test]
+    fn keeps_secret_arguments_out_of_logs() {
+        let dir = TempDir::new("secrets");
+        let path = dir.file("autolock.log");
+        let mut engine = configured_engine(&[("presets", "secrets")]);
+        engine.log_level = LogLevel::Debug;
+        engine.open_log_file(&path);
+        engine.update(running(1, "pass show bank/login"));
+        assert_eq!(mode_switches(&mut engine), vec![InputMode::Locked]);
+        engine.pipe(payload("panes"));
+        let log = std::fs::read_to_string(&path).unwrap();
+        assert!(log.contains("pane 1 runs `pass [redacted]` (trigger `pass`)"));
+        assert!(!log.contains("bank/login"));
+        assert!(!engine.format_trace().contains("bank/login"));
+        assert!(!engine.format_detected_commands().contains("bank/login"));
+    }
+
+    #[test]
+    fn keeps_secret_arguments_out_of_pane_titles() {
+        let dir = TempDir::new("titles");
+        let path = dir.file("autolock.log");
+        let mut engine = configured_engine(&[("presets", "secrets")]);
+        engine.log_level = LogLevel::Debug;
+        engine.open_log_file(&path);
+        engine.update(Event::PaneUpdate(manifest(vec![vec![
+            titled_pane(1, true, "pass show bank/login"),
+            pane(2, false),
+        ]])));
+        let log = std::fs::read_to_string(&path).unwrap();
+        assert!(log.contains("Pane renamed: `pass [redacted]`"));
+        assert!(!log.contains("bank/login"));
+    }
+
+    #[test]
+    fn reports_unknown_presets() {
+        let mut engine = Engine::default();
+        engine.load(BTreeMap::from([(
+            "presets".to_string(),
+            "secrets|paranoid".to_string(),
+        )]));
+        assert_eq!(
+            engine.configuration_errors(),
+            ["`presets` may only list `secrets`, not `paranoid`; ignoring it."]
+        );
+    }
+
+    #[test]
+    fn forwards_log_lines_to_a_plugin() {
+        let mut engine = Engine::default();
+        engine.load(BTreeMap::from([
+            ("log_level".to_string(), "info".to_string()),
+            ("log_to_plugin".to_string(), "file:/viewer.wasm".to_string()),
+        ]));
+        let actions = engine.take_actions();
+        assert!(matches!(
+            &actions[0],
+            Action::RequestPermissions(permissions)
+                if permissions.contains(&PermissionType::MessageAndLaunchOtherPlugins)
+        ));
+        assert!(actions.iter().any(|action| matches!(
+            action,
+            Action::PipeToPlugin { plugin_url, name, payload }
+                if plugin_url.as_deref() == Some("file:/viewer.wasm")
+                    && name == "autolock-log"
+                    && payload.contains("INFO Configuration loaded.")
+        )));
+        assert!(!engine
+            .take_actions()
+            .iter()
+            .any(|action| matches!(action, Action::PipeToPlugin { .. })));
+    }
+
+    #[test]
+    fn collapses_repeated_log_lines() {
+        let dir = TempDir::new("repeat");
+        let path = dir.file("autolock.log");
+        let mut engine = Engine {
+            log_level: LogLevel::Debug,
+            ..Default::default()
+        };
+        engine.open_log_file(&path);
+        for _ in 0..4 {
+            log!(engine, Debug, [pane = 1], "No command detected.");
+        }
+        log!(engine, Debug, [pane = 2], "No command detected.");
+        let log = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].ends_with("DEBUG No command detected."));
+        assert!(lines[1].ends_with("DEBUG (The previous message repeated 3 times.)"));
+        assert!(lines[2].ends_with("DEBUG No command detected."));
+    }
+
+    #[test]
+    fn shows_the_state_in_zjstatus() {
+        let mut engine = Engine::default();
+        engine.load(BTreeMap::from([(
+            "zjstatus_format".to_string(),
+            "{enabled} {mode} {trigger}".to_string(),
+        )]));
+        let zjstatus_payloads = |engine: &mut Engine| -> Vec<String> {
+            engine
+                .take_actions()
+                .into_iter()
+                .filter_map(|action| match action {
+                    Action::PipeToPlugin {
+                        plugin_url: None,
+                        name,
+                        payload,
+                    } if name == "zjstatus" => Some(payload),
+                    _ => None,
+                })
+                .collect()
+        };
+        assert_eq!(
+            zjstatus_payloads(&mut engine),
+            ["zjstatus::pipe::pipe_autolock::on normal "]
+        );
+        engine.update(Event::PermissionRequestResult(PermissionStatus::Granted));
+        assert_eq!(
+            zjstatus_payloads(&mut engine),
+            ["zjstatus::pipe::pipe_autolock::on normal "]
+        );
+        engine.update(Event::TabUpdate(vec![tab(0, true)]));
+        engine.update(Event::PaneUpdate(manifest(vec![vec![pane(1, true)]])));
+        engine.update(running(1, "vim"));
+        assert_eq!(
+            zjstatus_payloads(&mut engine),
+            ["zjstatus::pipe::pipe_autolock::on locked vim"]
+        );
+        engine.update(running(1, "vim"));
+        assert!(zjstatus_payloads(&mut engine).is_empty());
+    }
+
+    #[test]
+    fn waits_out_navigator_moves() {
+        let mut engine = initialized_engine();
+        engine.update(running(1, "vim"));
+        engine.take_actions();
+        engine.pipe(PipeMessage::new(
+            PipeSource::Cli("pipe-id".to_string()),
+            "move_focus",
+            &Some("left".to_string()),
+            &None,
+            false,
+        ));
+        let actions = engine.take_actions();
+        assert!(!actions.contains(&Action::ListClients));
+        assert!(!actions
+            .iter()
+            .any(|action| matches!(action, Action::SwitchToInputMode(_))));
+    }
+
+    #[test]
+    fn waits_for_a_new_session_to_settle() {
+        let session = |name: &str, is_current_session: bool| SessionInfo {
+            name: name.to_string(),
+            tabs: vec![tab(0, true)],
+            panes: manifest(vec![vec![pane(1, true)]]),
+            is_current_session,
+            ..Default::default()
+        };
+        let mut engine = initialized_engine();
+        engine.update(Event::SessionUpdate(vec![session("a", true)], vec![]));
+        engine.update(Event::SessionUpdate(
+            vec![session("a", false), session("b", true)],
+            vec![],
+        ));
+        engine.update(Event::PaneUpdate(manifest(vec![vec![pane(1, true)]])));
+        engine.update(running(1, "vim"));
+        assert!(mode_switches(&mut engine).is_empty());
+
+        engine.set_clock(1.0, 0.0);
+        engine.update(Event::Timer(0.5));
+        assert!(engine.take_actions().contains(&Action::ListClients));
+        engine.update(running(1, "vim"));
+        assert_eq!(mode_switches(&mut engine), [InputMode::Locked]);
+    }
+
+    #[test]
+    fn forgets_the_previous_session() {
+        let session = |name: &str, is_current_session: bool, pane_id: u32| SessionInfo {
+            name: name.to_string(),
+            tabs: vec![tab(0, true)],
+            panes: manifest(vec![vec![pane(pane_id, true)]]),
+            is_current_session,
+            ..Default::default()
+        };
+        let mut engine = initialized_engine();
+        engine.update(Event::SessionUpdate(vec![session("a", true, 1)], vec![]));
+        engine.update(Event::CommandPaneOpened(2, BTreeMap::new()));
+        engine.update(running(1, "vim"));
+        engine.update(mode(InputMode::Locked));
+        engine.take_actions();
+
+        engine.update(Event::SessionUpdate(
+            vec![session("a", false, 1), session("b", true, 5)],
+            vec![],
+        ));
+        assert!(engine.command_panes.is_empty());
+        assert_eq!(engine.latest_assessment, None);
+        assert_eq!(engine.focused_tab_position(), 0);
+        assert!(engine.is_focused_terminal_pane(5));
+    }
+
+    #[test]
+    fn keeps_what_it_knows_when_a_session_is_renamed() {
+        let session = |name: &str| SessionInfo {
+            name: name.to_string(),
+            tabs: vec![tab(0, true)],
+            panes: manifest(vec![vec![pane(1, true), pane(2, false)]]),
+            is_current_session: true,
+            ..Default::default()
+        };
+        let mut engine = initialized_engine();
+        engine.update(Event::SessionUpdate(vec![session("a")], vec![]));
+        engine.update(Event::CommandPaneOpened(2, BTreeMap::new()));
+        engine.update(running(1, "vim"));
+        engine.update(mode(InputMode::Locked));
+        engine.take_actions();
+
+        // `rename-session`: the old name is gone rather than merely not current.
+        engine.update(Event::SessionUpdate(vec![session("b")], vec![]));
+        assert_eq!(engine.latest_session_name.as_deref(), Some("b"));
+        assert!(engine.command_panes.contains_key(&2));
+        // Nothing to settle, either.
+        engine.update(running(1, "zsh"));
+        assert_eq!(mode_switches(&mut engine), [InputMode::Normal]);
+    }
+
+    #[test]
+    fn runs_hooks_on_lock_and_unlock() {
+        let mut engine = configured_engine(&[
+            ("on_lock", "notify-send locked"),
+            ("on_unlock", "notify-send unlocked"),
+        ]);
+        engine.pipe(payload("request-permissions"));
+        assert!(engine.take_actions().iter().any(|action| matches!(
+            action,
+            Action::RequestPermissions(permissions)
+                if permissions.contains(&PermissionType::RunCommands)
+        )));
+        let hooks = |engine: &mut Engine| -> Vec<String> {
+            engine
+                .take_actions()
+                .into_iter()
+                .filter_map(|action| match action {
+                    Action::RunCommand(command) => Some(command.join(" ")),
+                    _ => None,
+                })
+                .collect()
+        };
+        engine.update(running(1, "vim"));
+        assert_eq!(hooks(&mut engine), ["sh -c notify-send locked"]);
+        engine.update(mode(InputMode::Locked));
+        engine.update(running(1, "zsh"));
+        assert_eq!(hooks(&mut engine), ["sh -c notify-send unlocked"]);
+    }
+
+    #[test]
+    fn writes_escape_sequences_on_lock() {
+        let mut engine = configured_engine(&[
+            ("osc_on_lock", "\\033]2;locked\\007"),
+            ("osc_tty", "/dev/pts/3"),
+        ]);
+        engine.take_actions();
+        engine.update(running(1, "vim"));
+        assert!(engine.take_actions().contains(&Action::RunCommand(vec![
+            "sh".to_string(),
+            "-c".to_string(),
+            "printf '%b' \"$1\" > \"$2\"".to_string(),
+            "sh".to_string(),
+            "\\033]2;locked\\007".to_string(),
+            "/dev/pts/3".to_string(),
+        ])));
+        engine.update(mode(InputMode::Locked));
+        engine.update(running(1, "zsh"));
+        assert!(!engine
+            .take_actions()
+            .iter()
+            .any(|action| matches!(action, Action::RunCommand(_))));
+    }
+
+    #[test]
+    fn never_unlocks_in_inverse_mode() {
+        let mut engine = configured_engine(&[("inverse", "true")]);
+        engine.update(running(1, "vim"));
+        assert_eq!(mode_switches(&mut engine), [InputMode::Locked]);
+        engine.update(mode(InputMode::Locked));
+        engine.update(running(1, "zsh"));
+        assert!(mode_switches(&mut engine).is_empty());
+        engine.update(mode(InputMode::Normal));
+        engine.update(running(1, "vim"));
+        assert_eq!(mode_switches(&mut engine), [InputMode::Locked]);
+    }
+
+    #[test]
+    fn leaves_zellij_in_its_default_mode() {
+        assert_eq!(initialized_engine().safe_mode(), InputMode::Normal);
+        let mut engine = configured_engine(&[("inverse", "true")]);
+        assert_eq!(engine.safe_mode(), InputMode::Locked);
+        engine.update(Event::ModeUpdate(ModeInfo {
+            mode: InputMode::Locked,
+            base_mode: Some(InputMode::Normal),
+            ..Default::default()
+        }));
+        assert_eq!(engine.safe_mode(), InputMode::Normal);
+    }
+
+    #[test]
+    fn posts_switches_to_a_webhook() {
+        let mut engine = configured_engine(&[("webhook_url", "http://localhost:8123/autolock")]);
+        let posts = |engine: &mut Engine| -> Vec<String> {
+            engine
+                .take_actions()
+                .into_iter()
+                .filter_map(|action| match action {
+                    Action::PostJson { url, body } => Some(format!("{} {}", url, body)),
+                    _ => None,
+                })
+                .collect()
+        };
+        engine.update(running(1, "vim"));
+        assert_eq!(
+            posts(&mut engine),
+            [
+                r#"http://localhost:8123/autolock {"event":"lock","mode":"locked","trigger":"vim","pane":1,"timestamp":0.0}"#
+            ]
+        );
+        engine.update(mode(InputMode::Locked));
+        engine.update(running(1, "zsh"));
+        assert_eq!(
+            posts(&mut engine),
+            [
+                r#"http://localhost:8123/autolock {"event":"unlock","mode":"normal","trigger":null,"pane":1,"timestamp":0.0}"#
+            ]
+        );
+    }
+
+    #[test]
+    fn follows_the_editor_handshake() {
+        let editor = |command: &str| {
+            PipeMessage::new(
+                PipeSource::Cli("pipe-id".to_string()),
+                "autolock",
+                &Some(command.to_string()),
+                &Some(BTreeMap::from([("pane_id".to_string(), "1".to_string())])),
+                false,
+            )
+        };
+        let mut engine = initialized_engine();
+        engine.pipe(editor("editor-enter"));
+        let actions = engine.take_actions();
+        assert!(actions.contains(&Action::SwitchToInputMode(InputMode::Locked)));
+        assert!(!actions.contains(&Action::ListClients));
+        engine.update(mode(InputMode::Locked));
+        engine.update(running(1, "nvim"));
+        assert!(mode_switches(&mut engine).is_empty());
+
+        engine.pipe(editor("editor-leave"));
+        assert_eq!(mode_switches(&mut engine), [InputMode::Normal]);
+        engine.update(mode(InputMode::Normal));
+        engine.update(running(1, "nvim"));
+        engine.update(running(1, "zsh"));
+        assert!(mode_switches(&mut engine).is_empty());
+    }
+
+    #[test]
+    fn leaves_editor_panes_alone_while_disabled() {
+        let mut engine = initialized_engine();
+        engine.pipe(payload("editor-enter"));
+        engine.take_actions();
+        engine.update(mode(InputMode::Locked));
+        engine.pipe(PipeMessage::new(
+            PipeSource::Cli("pipe-id".to_string()),
+            "autolock:disable",
+            &None,
+            &None,
+            false,
+        ));
+        engine.update(mode(InputMode::Normal));
+        engine.take_actions();
+
+        engine.update(Event::PaneUpdate(manifest(vec![vec![
+            pane(1, false),
+            pane(2, true),
+        ]])));
+        engine.update(Event::PaneUpdate(manifest(vec![vec![
+            pane(1, true),
+            pane(2, false),
+        ]])));
+        assert!(mode_switches(&mut engine).is_empty());
+    }
+
+    #[test]
+    fn unlocks_for_a_while_when_asked() {
+        let mut engine = initialized_engine();
+        engine.update(running(1, "vim"));
+        engine.update(mode(InputMode::Locked));
+        engine.take_actions();
+
+        engine.pipe(payload("request-unlock 2"));
+        assert_eq!(mode_switches(&mut engine), [InputMode::Normal]);
+        engine.update(mode(InputMode::Normal));
+        engine.set_clock(1.0, 0.0);
+        engine.update(Event::Timer(1.0));
+        engine.update(running(1, "vim"));
+        assert!(mode_switches(&mut engine).is_empty());
+
+        engine.set_clock(2.5, 0.0);
+        engine.update(Event::Timer(1.0));
+        assert_eq!(mode_switches(&mut engine), [InputMode::Locked]);
+    }
+
+    #[test]
+    fn only_logs_switches_in_a_dry_run() {
+        let dir = TempDir::new("dry-run");
+        let path = dir.file("autolock.log");
+        let mut engine = configured_engine(&[("dry_run", "true")]);
+        engine.log_level = LogLevel::Info;
+        engine.open_log_file(&path);
+        engine.update(running(1, "vim"));
+        assert!(mode_switches(&mut engine).is_empty());
+        engine.update(running(1, "vim main.rs"));
+        engine.take_actions();
+        let log = std::fs::read_to_string(&path).unwrap();
+        let switches: Vec<&str> = log
+            .lines()
+            .filter(|line| line.contains("switched"))
+            .collect();
+        assert_eq!(switches.len(), 2);
+        assert!(switches[0].ends_with(
+            "INFO Dry run: would have switched from Normal to Locked mode: pane 1 runs `vim` (trigger `vim`), seen on ListClients."
+        ));
+        assert_eq!(engine.expected_mode, None);
+    }
+
+    #[test]
+    fn undoes_the_latest_switch_until_things_change() {
+        let mut engine = initialized_engine();
+        engine.pipe(payload("undo"));
+        assert!(mode_switches(&mut engine).is_empty());
+
+        engine.update(running(1, "vim"));
+        assert_eq!(mode_switches(&mut engine), [InputMode::Locked]);
+        engine.update(mode(InputMode::Locked));
+        engine.pipe(payload("undo"));
+        assert_eq!(mode_switches(&mut engine), [InputMode::Normal]);
+        engine.update(mode(InputMode::Normal));
+        engine.update(running(1, "vim"));
+        assert!(mode_switches(&mut engine).is_empty());
+
+        engine.update(running(1, "zsh"));
+        engine.update(running(1, "vim"));
+        assert_eq!(mode_switches(&mut engine), [InputMode::Locked]);
+    }
+
+    #[test]
+    fn locks_while_idle() {
+        let mut engine = configured_engine(&[("idle_lock_seconds", "60")]);
+        engine.update(Event::InputReceived);
+        engine.take_actions();
+
+        engine.set_clock(30.0, 0.0);
+        engine.update(Event::Timer(30.0));
+        assert!(mode_switches(&mut engine).is_empty());
+        engine.set_clock(61.0, 0.0);
+        engine.update(Event::Timer(30.0));
+        assert_eq!(mode_switches(&mut engine), [InputMode::Locked]);
+        engine.update(mode(InputMode::Locked));
+        // Nothing but input unlocks again.
+        engine.update(running(1, "zsh"));
+        assert!(mode_switches(&mut engine).is_empty());
+
+        engine.update(Event::InputReceived);
+        assert_eq!(mode_switches(&mut engine), [InputMode::Normal]);
+    }
+
+    #[test]
+    fn holds_off_while_an_overlay_is_open() {
+        let session_manager = PaneInfo {
+            id: 7,
+            is_plugin: true,
+            is_floating: true,
+            plugin_url: Some("zellij:session-manager".to_string()),
+            ..Default::default()
+        };
+        let mut engine = initialized_engine();
+        engine.update(Event::TabUpdate(vec![TabInfo {
+            are_floating_panes_visible: true,
+            ..tab(0, true)
+        }]));
+        engine.update(Event::PaneUpdate(manifest(vec![vec![
+            pane(1, true),
+            pane(2, false),
+            session_manager,
+        ]])));
+        engine.update(running(1, "vim"));
+        assert!(mode_switches(&mut engine).is_empty());
+
+        engine.update(Event::PaneUpdate(manifest(vec![vec![
+            pane(1, true),
+            pane(2, false),
+        ]])));
+        engine.update(running(1, "vim"));
+        assert_eq!(mode_switches(&mut engine), [InputMode::Locked]);
+    }
+
+    #[test]
+    fn follows_shell_integration() {
+        let shell = |command: &str| {
+            PipeMessage::new(
+                PipeSource::Cli("pipe-id".to_string()),
+                "autolock",
+                &Some(command.to_string()),
+                &Some(BTreeMap::from([("pane_id".to_string(), "1".to_string())])),
+                false,
+            )
+        };
+        let mut engine = initialized_engine();
+        engine.pipe(shell("cmd-start vim notes.md"));
+        let actions = engine.take_actions();
+        assert!(actions.contains(&Action::SwitchToInputMode(InputMode::Locked)));
+        assert!(!actions.contains(&Action::ListClients));
+        engine.update(mode(InputMode::Locked));
+        engine.update(running(1, "zsh"));
+        assert!(mode_switches(&mut engine).is_empty());
+
+        engine.pipe(shell("cmd-end"));
+        assert_eq!(mode_switches(&mut engine), [InputMode::Normal]);
+    }
+
+    #[test]
+    fn stays_off_in_embedded_sessions() {
+        let mut engine = configured_engine(&[("embedded_sessions", "vscode-*")]);
+        engine.update(Event::SessionUpdate(
+            vec![SessionInfo {
+                name: "vscode-project".to_string(),
+                is_current_session: true,
+                ..Default::default()
+            }],
+            vec![],
+        ));
+        engine.update(running(1, "vim"));
+        assert!(mode_switches(&mut engine).is_empty());
+
+        let mut engine = initialized_engine();
+        engine.pipe(payload("embedded"));
+        engine.update(running(1, "vim"));
+        assert!(mode_switches(&mut engine).is_empty());
+    }
+
+    #[test]
+    fn exports_the_state_to_a_file() {
+        let dir = TempDir::new("state-file");
+        let path = dir.file("state.json");
+        let mut engine = configured_engine(&[("state_file", &path)]);
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "{\"mode\":\"normal\",\"enabled\":true,\"trigger\":null}\n"
+        );
+        engine.update(running(1, "vim"));
+        engine.take_actions();
+        let state = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            state,
+            "{\"mode\":\"locked\",\"enabled\":true,\"trigger\":\"vim\"}\n"
+        );
+    }
+
+    #[test]
+    fn leaves_decisions_to_the_first_instance() {
+        let hello = |peer_id: u32, lock_trigger_cmds: &str| {
+            PipeMessage::new(
+                PipeSource::Plugin(peer_id),
+                INSTANCE_MESSAGE_NAME,
+                &Some(format!("{} {}", peer_id, lock_trigger_cmds)),
+                &None,
+                false,
+            )
+        };
+        let mut engine =
+            configured_engine(&[("coordinate_instances", "true"), ("triggers", "vim|nvim")]);
+        engine.set_instance_id(5);
+        assert!(engine.take_actions().contains(&Action::PipeToPlugin {
+            plugin_url: None,
+            name: INSTANCE_MESSAGE_NAME.to_string(),
+            payload: "5 vim|nvim".to_string(),
+        }));
+
+        // The other instance's triggers count too.
+        engine.pipe(hello(9, "lazygit"));
+        engine.update(running(1, "lazygit"));
+        assert_eq!(mode_switches(&mut engine), [InputMode::Locked]);
+        engine.update(mode(InputMode::Locked));
+
+        engine.pipe(hello(3, "vim"));
+        engine.update(running(1, "zsh"));
+        assert!(mode_switches(&mut engine).is_empty());
+
+        engine.set_clock(crate::instances::EXPIRY_SECONDS + 1.0, 0.0);
+        engine.pipe(hello(9, ""));
+        engine.update(Event::Timer(0.3));
+        engine.update(running(1, "zsh"));
+        assert_eq!(mode_switches(&mut engine), [InputMode::Normal]);
+        engine.update(mode(InputMode::Normal));
+        engine.update(running(1, "lazygit"));
+        assert!(mode_switches(&mut engine).is_empty());
+    }
+
+    #[test]
+    fn starts_afresh_when_a_client_attaches() {
+        let mut engine = initialized_engine();
+        engine.update(running(1, "vim"));
+        engine.update(mode(InputMode::Locked));
+        engine.take_actions();
+        assert_eq!(engine.current_client_id, Some(1));
+
+        // Detached while in vim, then attached again (as a new client) with pane 2 focused.
+        engine.update(Event::ListClients(vec![ClientInfo::new(
+            2,
+            PaneId::Terminal(2),
+            "zsh".to_string(),
+            true,
+        )]));
+        assert_eq!(engine.current_client_id, Some(2));
+        assert!(engine.is_focused_terminal_pane(2));
+        assert_eq!(mode_switches(&mut engine), vec![InputMode::Normal]);
+    }
+
+    #[test]
+    fn returns_to_the_pane_an_in_place_pane_replaced() {
+        let mut engine = initialized_engine();
+        engine.update(running(1, "vim"));
+        engine.update(mode(InputMode::Locked));
+        engine.take_actions();
+
+        // `zellij run --in-place`: pane 3 takes the place of pane 1 until it closes.
+        engine.update(Event::PaneUpdate(manifest(vec![vec![
+            PaneInfo {
+                is_suppressed: true,
+                ..pane(1, false)
+            },
+            pane(2, false),
+            pane(3, true),
+        ]])));
+        assert_eq!(engine.replaced_panes.get(&3), Some(&1));
+        engine.update(running(3, "zsh"));
+        assert_eq!(mode_switches(&mut engine), vec![InputMode::Normal]);
+        engine.update(mode(InputMode::Normal));
+
+        engine.update(Event::PaneClosed(PaneId::Terminal(3)));
+        assert!(engine.is_focused_terminal_pane(1));
+        assert!(engine.replaced_panes.is_empty());
+        assert!(engine.take_actions().contains(&Action::ListClients));
+        engine.update(running(1, "vim"));
+        assert_eq!(mode_switches(&mut engine), vec![InputMode::Locked]);
+    }
+
+    #[test]
+    fn treats_reused_pane_ids_as_new_panes() {
+        let mut engine = initialized_engine();
+        engine.update(Event::PaneUpdate(manifest(vec![vec![
+            pane(1, false),
+            pane(2, true),
+        ]])));
+        engine.update(running(2, "vim"));
+        engine.update(mode(InputMode::Locked));
+        engine.take_actions();
+
+        // Pane 2 closes, and zellij hands its id to the next pane opened.
+        engine.update(Event::PaneUpdate(manifest(vec![vec![pane(1, false)]])));
+        assert_eq!(engine.latest_assessment, None);
+        engine.update(Event::PaneUpdate(manifest(vec![vec![
+            pane(1, false),
+            pane(2, true),
+        ]])));
+        assert!(engine.take_actions().contains(&Action::ListClients));
+        engine.update(running(2, "zsh"));
+        assert_eq!(mode_switches(&mut engine), vec![InputMode::Normal]);
+    }
+
+    #[test]
+    fn follows_panes_moved_to_other_tabs() {
+        let mut engine = initialized_engine();
+        engine.update(running(1, "vim"));
+        engine.update(mode(InputMode::Locked));
+        engine.take_actions();
+
+        // `break-pane`: pane 1 moves to a tab of its own, taking the focus along.
+        engine.update(Event::PaneUpdate(manifest(vec![
+            vec![pane(2, true)],
+            vec![pane(1, true)],
+        ])));
+        assert_eq!(engine.focused_tab_position(), 1);
+        assert!(engine.is_focused_terminal_pane(1));
+        engine.update(running(1, "vim"));
+        assert!(mode_switches(&mut engine).is_empty());
+    }
+
+    #[test]
+    fn ignores_panes_in_background_tabs() {
+        let mut engine = initialized_engine();
+        engine.update(Event::TabUpdate(vec![tab(0, true), tab(1, false)]));
+        engine.update(Event::PaneUpdate(manifest(vec![
+            vec![pane(1, true), pane(2, false)],
+            vec![pane(3, true)],
+        ])));
+        engine.take_actions();
+        engine.update(running(3, "vim"));
+        assert!(mode_switches(&mut engine).is_empty());
+    }
+
+    #[test]
+    fn keeps_the_focused_tab_when_tabs_move() {
+        let named_tab = |position: usize, active: bool, name: &str| TabInfo {
+            name: name.to_string(),
+            ..tab(position, active)
+        };
+        let mut engine = initialized_engine();
+        engine.update(Event::TabUpdate(vec![
+            named_tab(0, true, "Tab #1"),
+            named_tab(1, false, "Tab #2"),
+        ]));
+        engine.update(Event::PaneUpdate(manifest(vec![
+            vec![pane(1, true), pane(2, false)],
+            vec![pane(3, true)],
+        ])));
+        engine.update(running(1, "vim"));
+        engine.update(mode(InputMode::Locked));
+        engine.take_actions();
+
+        // `move-tab right`: the focused tab now comes second, which the pane manifest has yet to
+        // show.
+        engine.update(Event::TabUpdate(vec![
+            named_tab(0, false, "Tab #2"),
+            named_tab(1, true, "Tab #1"),
+        ]));
+        assert!(engine.take_actions().is_empty());
+        engine.update(Event::PaneUpdate(manifest(vec![
+            vec![pane(3, true)],
+            vec![pane(1, true), pane(2, false)],
+        ])));
+        assert!(engine.take_actions().is_empty());
+        assert_eq!(engine.focused_tab_position(), 1);
+        assert!(engine.is_focused_terminal_pane(1));
+
+        // Moving it back, with the pane manifest first this time.
+        engine.update(Event::PaneUpdate(manifest(vec![
+            vec![pane(1, true), pane(2, false)],
+            vec![pane(3, true)],
+        ])));
+        engine.update(Event::TabUpdate(vec![
+            named_tab(0, true, "Tab #1"),
+            named_tab(1, false, "Tab #2"),
+        ]));
+        assert!(engine.take_actions().is_empty());
+        assert_eq!(engine.focused_tab_position(), 0);
+        assert!(engine.is_focused_terminal_pane(1));
+    }
+
+    #[test]
+    fn keeps_the_focused_tab_when_tabs_close() {
+        let mut engine = initialized_engine();
+        engine.update(Event::TabUpdate(vec![tab(0, false), tab(1, true)]));
+        engine.update(Event::PaneUpdate(manifest(vec![
+            vec![pane(1, true), pane(2, false)],
+            vec![pane(3, true)],
+        ])));
+        engine.update(running(3, "vim"));
+        assert_eq!(mode_switches(&mut engine), [InputMode::Locked]);
+        engine.update(mode(InputMode::Locked));
+
+        // Closing the first tab moves the focused one to its position, which the pane manifest
+        // shows before the TabUpdate does.
+        engine.update(Event::PaneUpdate(manifest(vec![vec![pane(3, true)]])));
+        assert_eq!(engine.focused_tab_position(), 0);
+        engine.update(Event::TabUpdate(vec![tab(0, true)]));
+        assert_eq!(engine.focused_tab_position(), 0);
+        assert!(engine.is_focused_terminal_pane(3));
+        engine.update(running(3, "vim"));
+        assert!(mode_switches(&mut engine).is_empty());
+    }
+
+    #[test]
+    fn leaves_other_modes_alone() {
+        let mut engine = initialized_engine();
+        engine.update(mode(InputMode::Pane));
+        engine.update(running(1, "vim"));
+        assert!(mode_switches(&mut engine).is_empty());
+    }
+
+    #[test]
+    fn stops_polling_when_disabled() {
+        let mut engine = initialized_engine();
+        engine.update(Event::InputReceived);
+        engine.pipe(payload("disable"));
+        engine.take_actions();
+        engine.update(Event::Timer(0.3));
+        engine.update(Event::InputReceived);
+        engine.update(Event::PaneClosed(PaneId::Terminal(1)));
+        assert!(engine.take_actions().is_empty());
+
+        engine.pipe(payload("enable"));
+        assert!(engine.take_actions().contains(&Action::ListClients));
+    }
+
+    #[test]
+    fn does_nothing_while_disabled() {
+        let mut engine = configured_engine(&[("is_enabled", "false")]);
+        engine.update(running(1, "vim"));
+        assert!(engine.take_actions().is_empty());
+    }
+}
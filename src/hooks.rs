@@ -0,0 +1,92 @@
+use zellij_tile::prelude::InputMode;
+
+/// What to run whenever autolock locks or unlocks zellij: shell commands, and escape sequences
+/// (e.g., a window title or a notification) written to `osc_tty`, the terminal zellij runs in.
+#[derive(Default)]
+pub struct Hooks {
+    pub on_lock: Option<String>,
+    pub on_unlock: Option<String>,
+    pub osc_on_lock: Option<String>,
+    pub osc_on_unlock: Option<String>,
+    pub osc_tty: Option<String>,
+}
+
+impl Hooks {
+    /// Whether any hooks are set, which need the RunCommands permission.
+    pub fn is_set(&self) -> bool {
+        self.on_lock.is_some() || self.on_unlock.is_some() || self.osc_tty.is_some()
+    }
+
+    /// The commands to run for a switch from `previous_mode` to `input_mode`: locking, unlocking,
+    /// or neither (e.g., from Normal to Pane).
+    pub fn commands(&self, previous_mode: InputMode, input_mode: InputMode) -> Vec<Vec<String>> {
+        let (hook, osc) = match input_mode {
+            InputMode::Locked => (&self.on_lock, &self.osc_on_lock),
+            _ if previous_mode == InputMode::Locked => (&self.on_unlock, &self.osc_on_unlock),
+            _ => return Vec::new(),
+        };
+        let mut commands = Vec::new();
+        if let Some(hook) = hook {
+            commands.push(vec!["sh".to_string(), "-c".to_string(), hook.clone()]);
+        }
+        // Zellij keeps plugin output to itself, so the sequence goes straight to the terminal.
+        // `printf %b` reads escapes such as `\033` and `\007` in the configured sequence.
+        if let (Some(osc), Some(osc_tty)) = (osc, &self.osc_tty) {
+            commands.push(vec![
+                "sh".to_string(),
+                "-c".to_string(),
+                "printf '%b' \"$1\" > \"$2\"".to_string(),
+                "sh".to_string(),
+                osc.clone(),
+                osc_tty.clone(),
+            ]);
+        }
+        commands
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_on_lock_and_unlock_only() {
+        let hooks = Hooks {
+            on_lock: Some("notify-send locked".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            hooks.commands(InputMode::Normal, InputMode::Locked),
+            [["sh", "-c", "notify-send locked"]]
+        );
+        assert!(hooks
+            .commands(InputMode::Locked, InputMode::Normal)
+            .is_empty());
+        assert!(hooks
+            .commands(InputMode::Normal, InputMode::Pane)
+            .is_empty());
+    }
+
+    #[test]
+    fn needs_a_terminal_for_escape_sequences() {
+        let mut hooks = Hooks {
+            osc_on_unlock: Some("\\033]2;unlocked\\007".to_string()),
+            ..Default::default()
+        };
+        assert!(hooks
+            .commands(InputMode::Locked, InputMode::Normal)
+            .is_empty());
+        hooks.osc_tty = Some("/dev/pts/3".to_string());
+        assert_eq!(
+            hooks.commands(InputMode::Locked, InputMode::Normal),
+            [[
+                "sh",
+                "-c",
+                "printf '%b' \"$1\" > \"$2\"",
+                "sh",
+                "\\033]2;unlocked\\007",
+                "/dev/pts/3"
+            ]]
+        );
+    }
+}
@@ -0,0 +1,161 @@
+use std::collections::BTreeMap;
+
+/// How often an instance tells the others it is still there, and how long they remember it.
+const HELLO_SECONDS: f64 = 5.0;
+pub const EXPIRY_SECONDS: f64 = 15.0;
+
+/// The other instances of the plugin, found through the hellos each one broadcasts with
+/// `coordinate_instances`: only the one with the lowest plugin id switches modes, so they do not
+/// fight over them, and it goes by the triggers of all of them.
+#[derive(Default)]
+pub struct Instances {
+    /// By plugin id.
+    peers: BTreeMap<u32, Peer>,
+    latest_hello_seconds: Option<f64>,
+}
+
+/// Another instance, as its latest hello described it.
+struct Peer {
+    heard_seconds: f64,
+    lock_trigger_cmds: Vec<String>,
+}
+
+/// What a hello told us that we did not know.
+#[derive(Debug, PartialEq)]
+pub enum News {
+    Found(u32),
+    ChangedTriggers(u32),
+}
+
+impl Instances {
+    /// Take in a hello: the sender's plugin id, then a space and its triggers, separated by `|`.
+    /// Our own hellos come back to us too, and are ignored.
+    pub fn hear(
+        &mut self,
+        payload: &str,
+        instance_id: Option<u32>,
+        clock_seconds: f64,
+    ) -> Option<News> {
+        let payload = payload.trim();
+        let (peer_id, lock_trigger_cmds) = payload.split_once(' ').unwrap_or((payload, ""));
+        let peer_id = peer_id
+            .parse::<u32>()
+            .ok()
+            .filter(|peer_id| Some(*peer_id) != instance_id)?;
+        let lock_trigger_cmds: Vec<String> = lock_trigger_cmds
+            .split('|')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect();
+        let peer = Peer {
+            heard_seconds: clock_seconds,
+            lock_trigger_cmds,
+        };
+        match self.peers.insert(peer_id, peer) {
+            None => {
+                // Let the newcomer know about us right away.
+                self.latest_hello_seconds = None;
+                Some(News::Found(peer_id))
+            }
+            Some(previous_peer)
+                if previous_peer.lock_trigger_cmds != self.peers[&peer_id].lock_trigger_cmds =>
+            {
+                Some(News::ChangedTriggers(peer_id))
+            }
+            Some(_) => None,
+        }
+    }
+
+    /// Forget the instances not heard from lately (e.g., unloaded), returning their ids.
+    pub fn expire(&mut self, clock_seconds: f64) -> Vec<u32> {
+        let expired: Vec<u32> = self
+            .peers
+            .iter()
+            .filter(|(_, peer)| clock_seconds - peer.heard_seconds > EXPIRY_SECONDS)
+            .map(|(peer_id, _)| *peer_id)
+            .collect();
+        for peer_id in &expired {
+            self.peers.remove(peer_id);
+        }
+        expired
+    }
+
+    /// The instance that switches modes instead of this one, if any.
+    pub fn leader(&self, instance_id: u32) -> Option<u32> {
+        self.peers
+            .keys()
+            .next()
+            .copied()
+            .filter(|peer_id| *peer_id < instance_id)
+    }
+
+    /// The triggers of the other instances.
+    pub fn lock_trigger_cmds(&self) -> impl Iterator<Item = &String> {
+        self.peers
+            .values()
+            .flat_map(|peer| peer.lock_trigger_cmds.iter())
+    }
+
+    /// The payload of a hello, when one is due.
+    pub fn hello(
+        &mut self,
+        instance_id: u32,
+        lock_trigger_cmds: &[String],
+        clock_seconds: f64,
+    ) -> Option<String> {
+        let is_due = self
+            .latest_hello_seconds
+            .is_none_or(|hello_seconds| clock_seconds - hello_seconds >= HELLO_SECONDS);
+        if !is_due {
+            return None;
+        }
+        self.latest_hello_seconds = Some(clock_seconds);
+        Some(format!("{} {}", instance_id, lock_trigger_cmds.join("|")))
+    }
+
+    /// Say hello again with the next chance, e.g., because our triggers changed.
+    pub fn hello_soon(&mut self) {
+        self.latest_hello_seconds = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn follows_the_lowest_id_heard_from_lately() {
+        let mut instances = Instances::default();
+        assert_eq!(instances.hear("5 vim", Some(5), 0.0), None);
+        assert_eq!(instances.hear("9 vim", Some(5), 0.0), Some(News::Found(9)));
+        assert_eq!(instances.leader(5), None);
+        assert_eq!(instances.hear("3", Some(5), 1.0), Some(News::Found(3)));
+        assert_eq!(instances.leader(5), Some(3));
+        assert_eq!(
+            instances.hear("9 vim|lazygit", Some(5), 10.0),
+            Some(News::ChangedTriggers(9))
+        );
+        assert_eq!(instances.hear("9 vim|lazygit", Some(5), 10.0), None);
+        assert_eq!(
+            instances.lock_trigger_cmds().collect::<Vec<_>>(),
+            ["vim", "lazygit"]
+        );
+
+        assert_eq!(instances.expire(EXPIRY_SECONDS + 2.0), [3]);
+        assert_eq!(instances.leader(5), None);
+    }
+
+    #[test]
+    fn says_hello_now_and_then() {
+        let mut instances = Instances::default();
+        let triggers = ["vim".to_string(), "git".to_string()];
+        assert_eq!(
+            instances.hello(5, &triggers, 0.0),
+            Some("5 vim|git".to_string())
+        );
+        assert_eq!(instances.hello(5, &triggers, 1.0), None);
+        assert!(instances.hello(5, &triggers, HELLO_SECONDS).is_some());
+        instances.hello_soon();
+        assert!(instances.hello(5, &triggers, HELLO_SECONDS).is_some());
+    }
+}
@@ -0,0 +1,178 @@
+use std::collections::VecDeque;
+
+/// How many switches the report looks back on.
+const CAPACITY: usize = 100;
+
+const STAGES: [&str; 4] = [
+    "event_to_timer",
+    "timer_to_detection",
+    "detection_to_switch",
+    "total",
+];
+
+/// The time from the event that led to a switch until the switch was sent, by stage: waiting for
+/// the timer (none for events that are acted on right away), detecting the command, and deciding.
+#[derive(Default)]
+pub struct Latency {
+    window: Option<Window>,
+    samples: VecDeque<Sample>,
+}
+
+struct Window {
+    event: String,
+    started_seconds: f64,
+    timer_seconds: Option<f64>,
+    detected_seconds: Option<f64>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sample {
+    pub event: String,
+    pub event_to_timer: f64,
+    pub timer_to_detection: f64,
+    pub detection_to_switch: f64,
+}
+
+impl Sample {
+    pub fn total(&self) -> f64 {
+        self.event_to_timer + self.timer_to_detection + self.detection_to_switch
+    }
+
+    /// The seconds taken by each of `STAGES`.
+    fn stages(&self) -> [f64; 4] {
+        [
+            self.event_to_timer,
+            self.timer_to_detection,
+            self.detection_to_switch,
+            self.total(),
+        ]
+    }
+}
+
+impl std::fmt::Display for Sample {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{:.0}ms from {}: event to timer {:.0}ms, timer to detection {:.0}ms, detection to switch {:.0}ms",
+            self.total() * 1000.0,
+            self.event,
+            self.event_to_timer * 1000.0,
+            self.timer_to_detection * 1000.0,
+            self.detection_to_switch * 1000.0
+        )
+    }
+}
+
+impl Latency {
+    /// Measure from this event on; a later one replaces it, since the latest event before a
+    /// switch (e.g., the `Enter` that starts a command) is the one the user waits on.
+    pub fn start(&mut self, event: String, clock_seconds: f64) {
+        self.window = Some(Window {
+            event,
+            started_seconds: clock_seconds,
+            timer_seconds: None,
+            detected_seconds: None,
+        });
+    }
+
+    pub fn timer_fired(&mut self, clock_seconds: f64) {
+        if let Some(window) = self.window.as_mut() {
+            window.timer_seconds.get_or_insert(clock_seconds);
+        }
+    }
+
+    pub fn detected(&mut self, clock_seconds: f64) {
+        if let Some(window) = self.window.as_mut() {
+            window.detected_seconds.get_or_insert(clock_seconds);
+        }
+    }
+
+    /// Ends the measurement with a switch, if anything was detected since it started.
+    pub fn switched(&mut self, clock_seconds: f64) -> Option<Sample> {
+        let window = self.window.take()?;
+        let detected_seconds = window.detected_seconds?;
+        let timer_seconds = window.timer_seconds.unwrap_or(window.started_seconds);
+        let sample = Sample {
+            event: window.event,
+            event_to_timer: timer_seconds - window.started_seconds,
+            timer_to_detection: detected_seconds - timer_seconds,
+            detection_to_switch: clock_seconds - detected_seconds,
+        };
+        if self.samples.len() == CAPACITY {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample.clone());
+        Some(sample)
+    }
+
+    /// Ends the measurement without a switch, once a decision found none was needed.
+    pub fn settled(&mut self) {
+        if self
+            .window
+            .as_ref()
+            .is_some_and(|window| window.detected_seconds.is_some())
+        {
+            self.window = None;
+        }
+    }
+
+    /// The median, 90th percentile, and maximum of each stage, in milliseconds.
+    pub fn format(&self) -> String {
+        let mut lines = vec![
+            format!("samples: {}", self.samples.len()),
+            "stage\tmedian_ms\tp90_ms\tmax_ms".to_string(),
+        ];
+        for (index, stage) in STAGES.iter().enumerate() {
+            let mut milliseconds: Vec<f64> = self
+                .samples
+                .iter()
+                .map(|sample| sample.stages()[index] * 1000.0)
+                .collect();
+            milliseconds.sort_by(f64::total_cmp);
+            let percentile = |percent: usize| {
+                milliseconds
+                    .get((milliseconds.len() * percent / 100).min(milliseconds.len().max(1) - 1))
+                    .copied()
+                    .unwrap_or(0.0)
+            };
+            lines.push(format!(
+                "{}\t{:.0}\t{:.0}\t{:.0}",
+                stage,
+                percentile(50),
+                percentile(90),
+                milliseconds.last().copied().unwrap_or(0.0)
+            ));
+        }
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn measures_each_stage() {
+        let mut latency = Latency::default();
+        for n in 0..10 {
+            let started_seconds = n as f64;
+            latency.start("InputReceived".to_string(), started_seconds);
+            latency.timer_fired(started_seconds + 0.3);
+            latency.detected(started_seconds + 0.31 + 0.01 * n as f64);
+            latency.switched(started_seconds + 0.4);
+        }
+        latency.start("PaneUpdate".to_string(), 20.0);
+        latency.detected(20.0);
+        latency.settled();
+        assert_eq!(latency.switched(20.1), None);
+        assert_eq!(
+            latency.format(),
+            "samples: 10\n\
+             stage\tmedian_ms\tp90_ms\tmax_ms\n\
+             event_to_timer\t300\t300\t300\n\
+             timer_to_detection\t60\t100\t100\n\
+             detection_to_switch\t50\t90\t90\n\
+             total\t400\t400\t400"
+        );
+    }
+}
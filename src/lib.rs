@@ -0,0 +1,32 @@
+/// Print to the zellij log at a `LogLevel`, if the configured `log_level` includes it, optionally
+/// with fields for structured output: `log!(self, Debug, [pane = pane_id], "...", ...)`.
+/// Compiled out without the `logging` feature.
+macro_rules! log {
+    ($engine:expr, $level:ident, [$($key:ident = $value:expr),* $(,)?], $($arg:tt)*) => {
+        if cfg!(feature = "logging") && $crate::log::LogLevel::$level <= $engine.log_level {
+            $engine.write_log(
+                $crate::log::LogLevel::$level,
+                &[$((stringify!($key), $value.to_string())),*],
+                format_args!($($arg)*),
+            );
+        }
+    };
+    ($engine:expr, $level:ident, $($arg:tt)*) => {
+        log!($engine, $level, [], $($arg)*)
+    };
+}
+
+mod command;
+mod drift;
+pub mod engine;
+mod hooks;
+mod instances;
+mod latency;
+mod log;
+pub mod panic_hook;
+mod persist;
+#[cfg(test)]
+mod testing;
+mod trigger;
+mod webhook;
+mod zjstatus;
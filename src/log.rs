@@ -0,0 +1,221 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+
+/// How much to print to the zellij log, from nothing to every step of every decision.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Off,
+    /// Something went wrong that the plugin cannot recover from.
+    Error,
+    /// Something went wrong, and the plugin works around it.
+    Warn,
+    /// Changes of state: configuration, enabling, sessions, clients, and mode switches.
+    Info,
+    /// What every assessment found and decided.
+    Debug,
+    /// How requests to zellij are scheduled.
+    Trace,
+}
+
+impl LogLevel {
+    pub fn parse(log_level: &str) -> Option<Self> {
+        match log_level.trim().to_lowercase().as_str() {
+            "off" => Some(LogLevel::Off),
+            "error" => Some(LogLevel::Error),
+            "warn" => Some(LogLevel::Warn),
+            "info" => Some(LogLevel::Info),
+            "debug" => Some(LogLevel::Debug),
+            "trace" => Some(LogLevel::Trace),
+            _ => None,
+        }
+    }
+}
+
+/// How each log line is written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    /// One JSON object per line, for filtering with tools like `jq`.
+    Json,
+}
+
+impl LogFormat {
+    pub fn parse(log_format: &str) -> Option<Self> {
+        match log_format.trim().to_lowercase().as_str() {
+            "text" => Some(LogFormat::Text),
+            "json" => Some(LogFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+/// A log file that is set aside once it grows past `max_bytes`, as `<path>.1`, `<path>.2`, and so
+/// on up to `keep` previous files, so a long session logging every decision stays bounded.
+pub struct LogFile {
+    path: String,
+    file: File,
+    bytes: u64,
+    max_bytes: u64,
+    keep: usize,
+}
+
+impl LogFile {
+    pub fn open(path: &str, max_bytes: u64, keep: usize) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let bytes = file.metadata()?.len();
+        Ok(LogFile {
+            path: path.to_string(),
+            file,
+            bytes,
+            max_bytes,
+            keep,
+        })
+    }
+
+    pub fn write_line(&mut self, line: &str) -> io::Result<()> {
+        if self.max_bytes > 0 && self.bytes > 0 && self.bytes + line.len() as u64 >= self.max_bytes
+        {
+            self.rotate()?;
+        }
+        writeln!(self.file, "{}", line)?;
+        self.bytes += line.len() as u64 + 1;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        for n in (1..self.keep).rev() {
+            let older = format!("{}.{}", self.path, n);
+            if fs::metadata(&older).is_ok() {
+                fs::rename(&older, format!("{}.{}", self.path, n + 1))?;
+            }
+        }
+        if self.keep > 0 {
+            fs::rename(&self.path, format!("{}.1", self.path))?;
+        }
+        self.file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)?;
+        self.bytes = 0;
+        Ok(())
+    }
+}
+
+/// A UTC timestamp such as `2024-05-01T12:30:05.250Z`, for the start of each text log line.
+pub fn format_timestamp(unix_seconds: f64) -> String {
+    let millis = (unix_seconds.max(0.0) * 1000.0) as u64;
+    let (days, millis_of_day) = (millis / 86_400_000, millis % 86_400_000);
+    // Civil date from days since 1970-01-01, in 400-year eras that start on March 1st.
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        millis_of_day / 3_600_000,
+        millis_of_day / 60_000 % 60,
+        millis_of_day / 1000 % 60,
+        millis_of_day % 1000
+    )
+}
+
+/// A log line as JSON: when and at what level, during which event, the message, and any fields
+/// (e.g., `pane`, `command`, `decision`) that describe what it is about.
+pub fn format_json(
+    timestamp: f64,
+    level: LogLevel,
+    event: &str,
+    message: &str,
+    fields: &[(&str, String)],
+) -> String {
+    let mut json = format!(
+        "{{\"timestamp\":{:.3},\"level\":{},\"event\":{},\"message\":{}",
+        timestamp,
+        json_string(&format!("{:?}", level).to_lowercase()),
+        json_string(event),
+        json_string(message)
+    );
+    for (key, value) in fields {
+        json.push_str(&format!(",{}:{}", json_string(key), json_string(value)));
+    }
+    json.push('}');
+    json
+}
+
+fn json_string(value: &str) -> String {
+    let mut json = String::with_capacity(value.len() + 2);
+    json.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            c if c.is_control() => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn rotates_log_files() {
+        let dir = TempDir::new("rotate");
+        let path = dir.file("autolock.log");
+        let mut log_file = LogFile::open(&path, 10, 2).unwrap();
+        for line in ["one", "two", "three", "four", "five"] {
+            log_file.write_line(line).unwrap();
+        }
+        let read = |path: &str| fs::read_to_string(path).unwrap_or_default();
+        assert_eq!(read(&path), "four\nfive\n");
+        assert_eq!(read(&format!("{}.1", path)), "three\n");
+        assert_eq!(read(&format!("{}.2", path)), "one\ntwo\n");
+        assert_eq!(read(&format!("{}.3", path)), "");
+    }
+
+    #[test]
+    fn formats_timestamps() {
+        assert_eq!(format_timestamp(0.0), "1970-01-01T00:00:00.000Z");
+        assert_eq!(format_timestamp(951_782_400.5), "2000-02-29T00:00:00.500Z");
+        assert_eq!(
+            format_timestamp(1_714_566_605.25),
+            "2024-05-01T12:30:05.250Z"
+        );
+    }
+
+    #[test]
+    fn formats_json_lines() {
+        assert_eq!(
+            format_json(
+                1.5,
+                LogLevel::Debug,
+                "ListClients",
+                "Detected \"vim\"\n",
+                &[
+                    ("pane", "3".to_string()),
+                    ("command", "vim\t-p".to_string())
+                ],
+            ),
+            r#"{"timestamp":1.500,"level":"debug","event":"ListClients","message":"Detected \"vim\"\n","pane":"3","command":"vim\u0009-p"}"#
+        );
+    }
+}
@@ -1,39 +1,30 @@
 use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use zellij_autolock::engine::{Action, Engine};
+use zellij_autolock::panic_hook;
 use zellij_tile::prelude::*;
 use zellij_tile::shim::list_clients;
 
-struct TabPane {
-    tab_pos: usize,
-    pane_id: u32,
-}
+/// Where runtime changes are remembered. `/data` belongs to one instance and lives in a folder
+/// the zellij server makes afresh each time it starts, so it is gone after a restart; `/cache` is
+/// kept in zellij's cache folder, shared by every instance of the plugin.
+const PERSIST_FILE: &str = "/cache/autolock-state";
 
+/// The plugin itself only relays events to the engine and carries out the actions it asks for.
 struct State {
-    is_enabled: bool,
-    permissions_granted: bool,
-    lock_trigger_cmds: Vec<String>,
-    reaction_seconds: f64,
-    timer_scheduled: bool,
-    latest_tab_pane: TabPane,
-    latest_mode: InputMode,
-    latest_running_command: String,
-    print_to_log: bool,
+    engine: Engine,
+    loaded_at: Instant,
+    /// The engine's `safe_mode`, for the panic hook.
+    safe_mode: Arc<Mutex<InputMode>>,
 }
 
 impl Default for State {
     fn default() -> Self {
         Self {
-            is_enabled: true,
-            permissions_granted: false,
-            lock_trigger_cmds: vec!["vim".to_string(), "nvim".to_string()],
-            reaction_seconds: 0.3,
-            timer_scheduled: false,
-            latest_tab_pane: TabPane {
-                tab_pos: usize::MAX,
-                pane_id: u32::MAX,
-            },
-            latest_mode: InputMode::Normal,
-            latest_running_command: "".to_string(),
-            print_to_log: false,
+            engine: Engine::default(),
+            loaded_at: Instant::now(),
+            safe_mode: Arc::new(Mutex::new(InputMode::Normal)),
         }
     }
 }
@@ -42,201 +33,107 @@ register_plugin!(State);
 
 impl ZellijPlugin for State {
     fn load(&mut self, configuration: BTreeMap<String, String>) {
-        request_permission(&[
-            // PermissionType::RunCommands,
-            PermissionType::ChangeApplicationState,
-            PermissionType::ReadApplicationState,
-        ]);
-        subscribe(&[
-            EventType::InputReceived,
-            EventType::ListClients,
-            EventType::ModeUpdate,
-            EventType::PaneUpdate,
-            EventType::PermissionRequestResult,
-            EventType::TabUpdate,
-            EventType::Timer,
-        ]);
-        if self.permissions_granted {
-            hide_self();
-        }
-        self.load_configuration(configuration);
+        // Keep the hook `register_plugin!` installed, which reports the panic to zellij.
+        std::panic::set_hook(panic_hook::leave_in_safe_mode(
+            std::panic::take_hook(),
+            self.safe_mode.clone(),
+            switch_to_input_mode,
+        ));
+        self.set_clock();
+        self.engine.set_instance_id(get_plugin_ids().plugin_id);
+        self.engine.set_persist_file(PERSIST_FILE);
+        self.engine.load(configuration);
+        self.run_actions();
     }
 
     fn update(&mut self, event: Event) -> bool {
-        match event {
-            Event::PermissionRequestResult(permission) => {
-                self.permissions_granted = match permission {
-                    PermissionStatus::Granted => true,
-                    PermissionStatus::Denied => false,
-                };
-                if self.permissions_granted {
-                    hide_self();
-                }
-            }
-
-            Event::ModeUpdate(mode_info) => {
-                self.latest_mode = mode_info.mode;
-                self.start_timer();
-            }
-
-            Event::InputReceived => {
-                self.start_timer();
-            }
-
-            Event::TabUpdate(tab_info) => {
-                if let Some(tab) = get_focused_tab(&tab_info) {
-                    if tab.position != self.latest_tab_pane.tab_pos {
-                        self.latest_tab_pane = TabPane {
-                            tab_pos: tab.position,
-                            pane_id: u32::MAX,
-                        };
-                    }
-                }
-            }
-
-            Event::PaneUpdate(pane_manifest) => {
-                let focused_pane =
-                    get_focused_pane(self.latest_tab_pane.tab_pos, &pane_manifest).clone();
-
-                if let Some(pane) = focused_pane {
-                    if pane.id != self.latest_tab_pane.pane_id {
-                        self.latest_tab_pane = TabPane {
-                            tab_pos: self.latest_tab_pane.tab_pos,
-                            pane_id: pane.id,
-                        };
-
-                        list_clients();
-                    }
-                }
-            }
-
-            Event::ListClients(clients) => {
-                if self.is_enabled {
-                    if let Some(current_client) = clients.iter().find(|client| {
-                        client.is_current_client && !client.running_command.is_empty()
-                    }) {
-                        let running_command = current_client.running_command.trim().to_string();
-
-                        let mut is_trigger_cmd = false;
-
-                        if running_command != "N/A" {
-                            let running_command_exe =
-                                running_command.split_whitespace().collect::<Vec<_>>()[0]
-                                    .split('/')
-                                    .last()
-                                    .unwrap_or("")
-                                    .to_string();
-
-                            is_trigger_cmd = self.lock_trigger_cmds.contains(&running_command)
-                                || self.lock_trigger_cmds.contains(&running_command_exe);
-
-                            if self.print_to_log {
-                                eprintln!(
-                                    "[autolock] Detected command: `{}`; Executable: `{}`; Is trigger? {}.",
-                                    running_command,
-                                    running_command_exe,
-                                    is_trigger_cmd,
-                                );
-                            }
-                        } else if self.print_to_log {
-                            eprintln!("[autolock] No command detected.");
-                        }
-
-                        let target_input_mode = if is_trigger_cmd {
-                            InputMode::Locked
-                        } else if self.latest_mode == InputMode::Locked {
-                            InputMode::Normal
-                        } else {
-                            self.latest_mode
-                        };
-
-                        if self.latest_mode != target_input_mode
-                            && (self.latest_mode == InputMode::Locked
-                                || self.latest_mode == InputMode::Normal)
-                        {
-                            switch_to_input_mode(&target_input_mode);
-                        }
-
-                        if running_command != self.latest_running_command {
-                            self.latest_running_command = running_command;
-                            self.start_timer();
-                        }
-                    }
-                }
-            }
-
-            Event::Timer(_t) => {
-                list_clients();
-                self.timer_scheduled = false;
-            }
-
-            _ => {}
-        }
-        return false; // No need to render UI.
+        self.set_clock();
+        let should_render = self.engine.update(event);
+        self.run_actions();
+        should_render
     }
 
     fn pipe(&mut self, pipe_message: PipeMessage) -> bool {
-        if let Some(payload) = pipe_message.payload {
-            let action = payload.to_string();
+        self.set_clock();
+        let should_render = self.engine.pipe(pipe_message);
+        self.run_actions();
+        should_render
+    }
 
-            if action == "enable" {
-                self.is_enabled = true;
-                if self.print_to_log {
-                    eprintln!("[autolock] Enabled");
-                }
-            } else if action == "disable" {
-                self.is_enabled = false;
-                if self.print_to_log {
-                    eprintln!("[autolock] Disabled");
-                }
-            } else if action == "toggle" {
-                self.is_enabled = !self.is_enabled;
-                if self.print_to_log {
-                    eprintln!("[autolock] Enabled: {}", self.is_enabled);
-                }
+    #[cfg(feature = "ui")]
+    fn render(&mut self, _rows: usize, _cols: usize) {
+        if self.engine.permissions_denied() {
+            println!("zellij-autolock cannot work without these permissions:");
+            println!();
+            println!("  - ReadApplicationState: to see which command runs in the focused pane");
+            println!("  - ChangeApplicationState: to switch between Normal and Locked modes");
+            println!();
+            println!("Press `r` to request them again, or send the `request-permissions` payload.");
+        } else if !self.engine.configuration_errors().is_empty() {
+            println!("zellij-autolock did not understand some of its configuration:");
+            println!();
+            for error in self.engine.configuration_errors() {
+                println!("  - {}", error);
+            }
+            println!();
+            println!("Fix these settings and reload the plugin.");
+        } else {
+            println!("zellij-autolock stats:");
+            println!();
+            for line in self.engine.format_stats().lines() {
+                println!("  {}", line);
             }
         }
-
-        if self.is_enabled {
-            list_clients();
-            self.start_timer();
-        }
-
-        return false; // No need to render UI.
     }
-
-    fn render(&mut self, _rows: usize, _cols: usize) {}
 }
 
 impl State {
-    fn load_configuration(&mut self, configuration: BTreeMap<String, String>) {
-        if let Some(is_enabled) = configuration.get("is_enabled") {
-            self.is_enabled = matches!(is_enabled.trim(), "true" | "t" | "y" | "1");
-        }
-        if let Some(lock_trigger_cmds) = configuration.get("triggers") {
-            self.lock_trigger_cmds = lock_trigger_cmds
-                .split('|')
-                .map(|s| s.trim().to_string())
-                .collect();
-        }
-        if let Some(reaction_seconds) = configuration.get("reaction_seconds") {
-            self.reaction_seconds = reaction_seconds.parse::<f64>().unwrap();
-        }
-        if let Some(print_to_log) = configuration.get("print_to_log") {
-            self.print_to_log = matches!(print_to_log.trim(), "true" | "t" | "y" | "1");
-        }
+    fn set_clock(&mut self) {
+        let unix_seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0.0, |duration| duration.as_secs_f64());
+        self.engine
+            .set_clock(self.loaded_at.elapsed().as_secs_f64(), unix_seconds);
+    }
 
-        if self.print_to_log {
-            eprintln!("[autolock] Configuration loaded.");
-            eprintln!("[autolock] Enabled: {}", self.is_enabled);
-            eprintln!("[autolock] Trigger commands: {:?}", self.lock_trigger_cmds);
-            eprintln!("[autolock] Reaction seconds: {}", self.reaction_seconds);
+    fn run_actions(&mut self) {
+        for action in self.engine.take_actions() {
+            match action {
+                Action::RequestPermissions(permissions) => request_permission(&permissions),
+                Action::Subscribe(event_types) => subscribe(&event_types),
+                Action::Unsubscribe(event_types) => unsubscribe(&event_types),
+                Action::ListClients => list_clients(),
+                Action::SetTimeout(seconds) => set_timeout(seconds),
+                Action::SwitchToInputMode(input_mode) => switch_to_input_mode(&input_mode),
+                Action::ShowSelf => show_self(true),
+                Action::HideSelf => hide_self(),
+                Action::PipeOutput { name, output } => cli_pipe_output(&name, &output),
+                Action::RunCommand(command) => {
+                    let command: Vec<&str> = command.iter().map(String::as_str).collect();
+                    run_command(&command, BTreeMap::new());
+                }
+                Action::PostJson { url, body } => web_request(
+                    url,
+                    HttpVerb::Post,
+                    BTreeMap::from([("Content-Type".to_string(), "application/json".to_string())]),
+                    body.into_bytes(),
+                    BTreeMap::new(),
+                ),
+                Action::PipeToPlugin {
+                    plugin_url,
+                    name,
+                    payload,
+                } => {
+                    let message = MessageToPlugin::new(name).with_payload(payload);
+                    pipe_message_to_plugin(match plugin_url {
+                        Some(plugin_url) => message.with_plugin_url(plugin_url),
+                        None => message,
+                    })
+                }
+            }
         }
-    }
-    fn start_timer(&mut self) {
-        if self.is_enabled && !self.timer_scheduled {
-            set_timeout(self.reaction_seconds);
-            self.timer_scheduled = true;
+        if let Ok(mut safe_mode) = self.safe_mode.lock() {
+            *safe_mode = self.engine.safe_mode();
         }
     }
 }
@@ -0,0 +1,53 @@
+use std::panic::PanicHookInfo;
+use std::sync::{Arc, Mutex, PoisonError};
+use zellij_tile::prelude::InputMode;
+
+/// What `std::panic::set_hook` takes and `std::panic::take_hook` gives back.
+pub type PanicHook = Box<dyn Fn(&PanicHookInfo<'_>) + Sync + Send + 'static>;
+
+/// Plugins cannot unwind, so a panic ends the plugin, and nothing is left to switch modes later:
+/// leave zellij in `safe_mode` (kept up to date as the configuration and zellij's own default are
+/// learned), then hand the panic to `previous`, which reports it to zellij.
+pub fn leave_in_safe_mode(
+    previous: PanicHook,
+    safe_mode: Arc<Mutex<InputMode>>,
+    switch_to_input_mode: fn(&InputMode),
+) -> PanicHook {
+    Box::new(move |panic_info| {
+        let mode = *safe_mode.lock().unwrap_or_else(PoisonError::into_inner);
+        eprintln!(
+            "zellij-autolock stopped after an internal error, leaving zellij in {:?} mode: {}",
+            mode, panic_info
+        );
+        switch_to_input_mode(&mode);
+        previous(panic_info);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static SWITCHES: Mutex<Vec<InputMode>> = Mutex::new(Vec::new());
+    static REPORTS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    #[test]
+    fn switches_modes_and_still_reports_the_panic() {
+        let safe_mode = Arc::new(Mutex::new(InputMode::Normal));
+        let default_hook = std::panic::take_hook();
+        std::panic::set_hook(leave_in_safe_mode(
+            Box::new(|panic_info| REPORTS.lock().unwrap().push(panic_info.to_string())),
+            safe_mode.clone(),
+            |input_mode| SWITCHES.lock().unwrap().push(*input_mode),
+        ));
+        *safe_mode.lock().unwrap() = InputMode::Locked;
+        let result = std::panic::catch_unwind(|| panic!("bad setting"));
+        std::panic::set_hook(default_hook);
+
+        assert!(result.is_err());
+        assert_eq!(*SWITCHES.lock().unwrap(), [InputMode::Locked]);
+        let reports = REPORTS.lock().unwrap();
+        assert_eq!(reports.len(), 1);
+        assert!(reports[0].contains("bad setting"));
+    }
+}
@@ -0,0 +1,76 @@
+/// What the plugin remembers across reloads and restarts of zellij, one `key value` line each, so
+/// that what the user changed at runtime wins over the configuration it was loaded with.
+#[derive(Debug, Default, PartialEq)]
+pub struct Persisted {
+    /// Set by `enable`, `disable`, and `toggle`.
+    pub is_enabled: Option<bool>,
+    /// From `add-trigger` and `remove-trigger`, applied on top of the configured triggers.
+    pub added_triggers: Vec<String>,
+    pub removed_triggers: Vec<String>,
+}
+
+impl Persisted {
+    /// Lines that are not understood (e.g., from a newer version) are skipped.
+    pub fn parse(contents: &str) -> Self {
+        let mut persisted = Persisted::default();
+        for line in contents.lines() {
+            match line.trim().split_once(' ') {
+                Some(("enabled", value)) => {
+                    persisted.is_enabled = match value.trim() {
+                        "true" => Some(true),
+                        "false" => Some(false),
+                        _ => None,
+                    };
+                }
+                Some(("add-trigger", trigger)) => {
+                    persisted.added_triggers.push(trigger.trim().to_string());
+                }
+                Some(("remove-trigger", trigger)) => {
+                    persisted.removed_triggers.push(trigger.trim().to_string());
+                }
+                _ => {}
+            }
+        }
+        persisted
+    }
+
+    pub fn format(&self) -> String {
+        let mut lines = Vec::new();
+        if let Some(is_enabled) = self.is_enabled {
+            lines.push(format!("enabled {}", is_enabled));
+        }
+        for trigger in &self.added_triggers {
+            lines.push(format!("add-trigger {}", trigger));
+        }
+        for trigger in &self.removed_triggers {
+            lines.push(format!("remove-trigger {}", trigger));
+        }
+        lines
+            .into_iter()
+            .map(|line| format!("{}\n", line))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_what_it_formats() {
+        let persisted = Persisted {
+            is_enabled: Some(false),
+            added_triggers: vec!["git log*".to_string()],
+            removed_triggers: vec!["fzf".to_string()],
+        };
+        assert_eq!(
+            persisted.format(),
+            "enabled false\nadd-trigger git log*\nremove-trigger fzf\n"
+        );
+        assert_eq!(Persisted::parse(&persisted.format()), persisted);
+        assert_eq!(
+            Persisted::parse("colour blue\nenabled maybe\n"),
+            Persisted::default()
+        );
+    }
+}
@@ -0,0 +1,24 @@
+use std::path::PathBuf;
+
+/// A folder of a test's own for the files it writes, removed with them once dropped, even when
+/// the test fails. Tests run in parallel, so each names its own.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("autolock-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+
+    pub fn file(&self, file_name: &str) -> String {
+        self.0.join(file_name).display().to_string()
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
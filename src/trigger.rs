@@ -0,0 +1,142 @@
+use crate::command;
+use regex::RegexSet;
+use std::collections::HashSet;
+
+/// Tools that handle secrets, for the `secrets` preset: `pass`, `gopass`, Bitwarden's `bw`,
+/// and `age`.
+pub const SECRETS_PRESET: [&str; 4] = ["pass", "gopass", "bw", "age"];
+
+/// The commands from the `triggers` setting, compiled once when the setting is loaded rather than
+/// re-interpreted for every command they are matched against. Literal triggers are looked up in
+/// a set, and patterns are matched all at once, so a longer list costs little per command.
+#[derive(Default)]
+pub struct Triggers {
+    literals: HashSet<String>,
+    /// Patterns where `*` stands for any characters and `?` for any single character, as a single
+    /// set, along with the trigger each was compiled from.
+    globs: RegexSet,
+    glob_triggers: Vec<String>,
+}
+
+impl Triggers {
+    pub fn compile(triggers: &[String]) -> Self {
+        let mut literals = HashSet::new();
+        let mut glob_triggers = Vec::new();
+        for trigger in triggers {
+            if trigger.contains(['*', '?']) {
+                glob_triggers.push(trigger.clone());
+            } else {
+                literals.insert(trigger.clone());
+            }
+        }
+        let globs = RegexSet::new(glob_triggers.iter().map(|trigger| glob_regex(trigger)))
+            .expect("escaped globs are valid regular expressions");
+        Triggers {
+            literals,
+            globs,
+            glob_triggers,
+        }
+    }
+
+    /// The trigger matched by a command line, or by any command it chains or pipes into.
+    pub fn matching_command(&self, running_command: &str) -> Option<&str> {
+        let matching = |segment: &str| self.matching(segment, &command::executable(segment));
+        matching(running_command).or_else(|| {
+            command::segments(running_command)
+                .iter()
+                .find_map(|segment| matching(segment))
+        })
+    }
+
+    /// The trigger that a command, or the executable of a command, matches, if any. Of several
+    /// matching patterns, the one listed first wins.
+    pub fn matching(&self, command: &str, executable: &str) -> Option<&str> {
+        self.literals
+            .get(command)
+            .or_else(|| self.literals.get(executable))
+            .map(String::as_str)
+            .or_else(|| {
+                let first_match = |text: &str| self.globs.matches(text).iter().next();
+                let index = match (first_match(command), first_match(executable)) {
+                    (Some(a), Some(b)) => a.min(b),
+                    (index, None) | (None, index) => index?,
+                };
+                Some(self.glob_triggers[index].as_str())
+            })
+    }
+}
+
+/// A regular expression for the whole of a text matching `trigger`, with anything other than
+/// `*` and `?` taken literally.
+fn glob_regex(trigger: &str) -> String {
+    let mut regex = String::from("^(?s:");
+    for c in trigger.chars() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push_str(")$");
+    regex
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(trigger: &str, command: &str) -> bool {
+        Triggers::compile(&[trigger.to_string()])
+            .matching(command, &crate::command::executable(command))
+            .is_some()
+    }
+
+    #[test]
+    fn literals_match_commands_or_executables() {
+        assert!(matches("vim", "vim"));
+        assert!(matches("vim", "/usr/bin/vim main.rs"));
+        assert!(matches("git log", "git log"));
+        assert!(!matches("vim", "nvim"));
+        assert!(!matches("git log", "git log -p"));
+    }
+
+    #[test]
+    fn globs_match_commands_or_executables() {
+        assert!(matches("*vim", "nvim main.rs"));
+        assert!(matches("*vim", "vim"));
+        assert!(matches("git log*", "git log -p"));
+        assert!(matches("python?", "python3 -i"));
+        assert!(matches("a*b*c", "aXbYbZc"));
+        assert!(!matches("*vim", "vimdiff"));
+        assert!(!matches("python?", "python"));
+        assert!(!matches("git log*", "git status"));
+    }
+
+    #[test]
+    fn names_the_matching_trigger() {
+        let triggers = Triggers::compile(&["vim".to_string(), "*top".to_string()]);
+        assert_eq!(triggers.matching("vim main.rs", "vim"), Some("vim"));
+        assert_eq!(triggers.matching("htop", "htop"), Some("*top"));
+        assert_eq!(triggers.matching("zsh", "zsh"), None);
+    }
+
+    #[test]
+    fn matches_any_of_many_triggers() {
+        let triggers: Vec<String> = (0..500)
+            .map(|n| format!("tool{}", n))
+            .chain(["*vim".to_string()])
+            .collect();
+        let triggers = Triggers::compile(&triggers);
+        assert!(triggers.matching("tool250 --flag", "tool250").is_some());
+        assert!(triggers.matching("nvim", "nvim").is_some());
+        assert!(triggers.matching("tool500", "tool500").is_none());
+    }
+
+    #[test]
+    fn prefers_the_first_matching_pattern() {
+        let triggers = Triggers::compile(&["n*".to_string(), "*vim".to_string()]);
+        assert_eq!(triggers.matching("/usr/bin/nvim", "nvim"), Some("n*"));
+        assert_eq!(triggers.matching("vim.tiny", "vim.tiny"), None);
+        assert_eq!(triggers.matching("gvim", "gvim"), Some("*vim"));
+    }
+}
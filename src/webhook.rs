@@ -0,0 +1,51 @@
+use zellij_autolock_messages::{SwitchEvent, SwitchEventKind};
+use zellij_tile::prelude::InputMode;
+
+/// Where to POST a JSON notice of every lock and unlock, e.g., for home automation.
+pub struct Webhook {
+    pub url: String,
+}
+
+impl Webhook {
+    /// The notice of a switch to `input_mode`, which either locks zellij or unlocks it.
+    pub fn body(
+        &self,
+        input_mode: InputMode,
+        trigger: Option<String>,
+        pane: u32,
+        timestamp: f64,
+    ) -> String {
+        SwitchEvent {
+            event: if input_mode == InputMode::Locked {
+                SwitchEventKind::Lock
+            } else {
+                SwitchEventKind::Unlock
+            },
+            mode: format!("{:?}", input_mode).to_lowercase(),
+            trigger,
+            pane,
+            timestamp,
+        }
+        .to_json()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describes_the_switch() {
+        let webhook = Webhook {
+            url: "http://localhost:8123/autolock".to_string(),
+        };
+        assert_eq!(
+            webhook.body(InputMode::Locked, Some("vim".to_string()), 1, 1.5),
+            r#"{"event":"lock","mode":"locked","trigger":"vim","pane":1,"timestamp":1.5}"#
+        );
+        assert_eq!(
+            webhook.body(InputMode::Normal, None, 2, 0.0),
+            r#"{"event":"unlock","mode":"normal","trigger":null,"pane":2,"timestamp":0.0}"#
+        );
+    }
+}
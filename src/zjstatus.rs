@@ -0,0 +1,72 @@
+use zellij_tile::prelude::InputMode;
+
+/// What to show in zjstatus, sent through its `zjstatus::pipe::pipe_autolock` protocol, which the
+/// bar picks up as the `pipe_autolock` widget.
+pub struct Zjstatus {
+    /// With `{mode}`, `{enabled}`, and `{trigger}` filled in.
+    format: String,
+    /// What was sent last, so that only changes are sent.
+    output: Option<String>,
+}
+
+impl Zjstatus {
+    pub fn new(format: String) -> Self {
+        Self {
+            format,
+            output: None,
+        }
+    }
+
+    /// The payload for zjstatus, if what it shows has changed. The trigger only shows while it
+    /// holds zellij locked.
+    pub fn update(
+        &mut self,
+        mode: InputMode,
+        is_enabled: bool,
+        trigger: Option<&str>,
+    ) -> Option<String> {
+        let trigger = match trigger {
+            Some(trigger) if is_enabled && mode == InputMode::Locked => trigger,
+            _ => "",
+        };
+        let output = self
+            .format
+            .replace("{mode}", &format!("{:?}", mode).to_lowercase())
+            .replace("{enabled}", if is_enabled { "on" } else { "off" })
+            .replace("{trigger}", trigger);
+        if self.output.as_ref() == Some(&output) {
+            return None;
+        }
+        let payload = format!("zjstatus::pipe::pipe_autolock::{}", output);
+        self.output = Some(output);
+        Some(payload)
+    }
+
+    /// Send the next update even if nothing changed, e.g., because the last one was dropped.
+    pub fn resend(&mut self) {
+        self.output = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sends_only_changes() {
+        let mut zjstatus = Zjstatus::new("{enabled} {mode} {trigger}".to_string());
+        assert_eq!(
+            zjstatus.update(InputMode::Locked, true, Some("vim")),
+            Some("zjstatus::pipe::pipe_autolock::on locked vim".to_string())
+        );
+        assert_eq!(zjstatus.update(InputMode::Locked, true, Some("vim")), None);
+        assert_eq!(
+            zjstatus.update(InputMode::Locked, false, Some("vim")),
+            Some("zjstatus::pipe::pipe_autolock::off locked ".to_string())
+        );
+        zjstatus.resend();
+        assert!(zjstatus
+            .update(InputMode::Locked, false, Some("vim"))
+            .is_some());
+    }
+}
@@ -0,0 +1,257 @@
+//! The messages zellij-autolock accepts and emits, so that plugins talking to it (and the plugin
+//! itself) share one definition of them.
+//!
+//! Commands are sent as the payload of a pipe message to the plugin, e.g.,
+//! `MessagePlugin "autolock" {payload "toggle";}` or `zellij pipe --plugin autolock -- toggle`.
+//! Messages without a payload can carry the command in their name instead, after [`PIPE_NAME`]
+//! and a colon, e.g., `zellij pipe --name autolock:toggle`.
+
+use serde::{Deserialize, Serialize};
+
+/// The name of the plugin alias the README registers, and of the pipe messages meant for it.
+pub const PIPE_NAME: &str = "autolock";
+
+/// The pipe argument naming the pane an `editor-enter`, `editor-leave`, `cmd-start`, or `cmd-end`
+/// is about, i.e., the sender's `$ZELLIJ_PANE_ID`. Without it, the focused pane is assumed.
+pub const PANE_ID_ARG: &str = "pane_id";
+
+/// The name of the pipe messages carrying log lines to the plugin set in `log_to_plugin`. Each
+/// payload holds one or more lines, separated by newlines.
+pub const LOG_MESSAGE_NAME: &str = "autolock-log";
+
+/// The name of the pipe messages instances of the plugin broadcast to find each other, when
+/// `coordinate_instances` is set. Each payload is the sender's plugin id, then a space and its
+/// triggers, separated by `|`; the instance with the lowest id is the one that switches modes, by
+/// the triggers of all of them.
+pub const INSTANCE_MESSAGE_NAME: &str = "autolock-instance";
+
+/// A payload the plugin acts on: a name, followed by arguments for some. Any other payload (or
+/// none) asks for an immediate assessment of the focused pane.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    Enable,
+    Disable,
+    Toggle,
+    RequestPermissions,
+    /// Replies with whether autolock is enabled, the mode, and what it has done so far.
+    Status,
+    /// Replies with the command detected in each pane of the focused tab.
+    Panes,
+    /// Replies with the latest events and decisions.
+    DumpTrace,
+    /// Replies with the time spent locked and unlocked, the automatic switches, and the triggers
+    /// that locked most often.
+    Stats,
+    /// Replies with how long switches took, by stage, when `measure_latency` is set.
+    Latency,
+    /// Replies with a checklist of what autolock needs to work: permissions, events, usable
+    /// `list_clients` answers, and sound triggers.
+    Doctor,
+    /// Reverts the latest automatic switch, and leaves the mode alone until the focused pane or
+    /// its command changes.
+    Undo,
+    /// Adds a trigger for the rest of the session and, remembered, after reloads and restarts.
+    AddTrigger(String),
+    /// Removes a trigger, be it configured or added, the same way.
+    RemoveTrigger(String),
+    /// Sent by an editor as it starts or gains focus: its pane stays locked, without polling,
+    /// until the editor sends `EditorLeave`.
+    EditorEnter,
+    /// Sent by an editor as it exits or loses focus.
+    EditorLeave,
+    /// Sent by a tool (e.g., a picker inside an editor) that needs zellij's keys for a while:
+    /// unlocks for this many seconds, then locks again.
+    RequestUnlock(f64),
+    /// Sent by a shell's preexec hook with the command it is about to run. Panes whose shell
+    /// reports its commands are never polled.
+    CmdStart(String),
+    /// Sent by a shell's precmd hook, as it shows the prompt again.
+    CmdEnd,
+    /// Sent by a shell's init inside an IDE's terminal (e.g., when `$TERM_PROGRAM` is `vscode`):
+    /// the IDE already manages keybindings, so autolock turns itself off for the session.
+    Embedded,
+}
+
+impl Command {
+    pub fn parse(payload: &str) -> Option<Self> {
+        let payload = payload.trim();
+        let (name, argument) = match payload.split_once(char::is_whitespace) {
+            Some((name, argument)) => (name, argument.trim()),
+            None => (payload, ""),
+        };
+        let command = match name {
+            "enable" => Command::Enable,
+            "disable" => Command::Disable,
+            "toggle" => Command::Toggle,
+            "request-permissions" => Command::RequestPermissions,
+            "status" => Command::Status,
+            "panes" => Command::Panes,
+            "dump-trace" => Command::DumpTrace,
+            "stats" => Command::Stats,
+            "doctor" => Command::Doctor,
+            "latency" => Command::Latency,
+            "undo" => Command::Undo,
+            "editor-enter" => Command::EditorEnter,
+            "editor-leave" => Command::EditorLeave,
+            "cmd-end" => Command::CmdEnd,
+            "embedded" => Command::Embedded,
+            "request-unlock" => {
+                let seconds = argument.parse::<f64>().ok()?;
+                if !(seconds.is_finite() && seconds > 0.0) {
+                    return None;
+                }
+                return Some(Command::RequestUnlock(seconds));
+            }
+            "cmd-start" if !argument.is_empty() => {
+                return Some(Command::CmdStart(argument.to_string()));
+            }
+            "add-trigger" if !argument.is_empty() => {
+                return Some(Command::AddTrigger(argument.to_string()));
+            }
+            "remove-trigger" if !argument.is_empty() => {
+                return Some(Command::RemoveTrigger(argument.to_string()));
+            }
+            _ => return None,
+        };
+        if argument.is_empty() {
+            Some(command)
+        } else {
+            None
+        }
+    }
+}
+
+/// The payload to send, e.g., `request-unlock 5`.
+impl std::fmt::Display for Command {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Command::Enable => write!(f, "enable"),
+            Command::Disable => write!(f, "disable"),
+            Command::Toggle => write!(f, "toggle"),
+            Command::RequestPermissions => write!(f, "request-permissions"),
+            Command::Status => write!(f, "status"),
+            Command::Panes => write!(f, "panes"),
+            Command::DumpTrace => write!(f, "dump-trace"),
+            Command::Stats => write!(f, "stats"),
+            Command::Doctor => write!(f, "doctor"),
+            Command::Latency => write!(f, "latency"),
+            Command::Undo => write!(f, "undo"),
+            Command::AddTrigger(trigger) => write!(f, "add-trigger {}", trigger),
+            Command::RemoveTrigger(trigger) => write!(f, "remove-trigger {}", trigger),
+            Command::EditorEnter => write!(f, "editor-enter"),
+            Command::EditorLeave => write!(f, "editor-leave"),
+            Command::RequestUnlock(seconds) => write!(f, "request-unlock {}", seconds),
+            Command::CmdStart(command) => write!(f, "cmd-start {}", command),
+            Command::CmdEnd => write!(f, "cmd-end"),
+            Command::Embedded => write!(f, "embedded"),
+        }
+    }
+}
+
+/// What the plugin writes to `state_file` as JSON whenever any of it changes.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct State {
+    /// The current mode, e.g., `locked` or `normal`.
+    pub mode: String,
+    pub enabled: bool,
+    /// The trigger that locked zellij, if it is locked because of one.
+    pub trigger: Option<String>,
+}
+
+impl State {
+    pub fn to_json(&self) -> String {
+        // A struct of strings and booleans always serializes.
+        serde_json::to_string(self).unwrap()
+    }
+
+    pub fn from_json(json: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(json)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SwitchEventKind {
+    Lock,
+    Unlock,
+}
+
+/// What the plugin POSTs to `webhook_url` as JSON whenever it locks or unlocks zellij.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SwitchEvent {
+    pub event: SwitchEventKind,
+    /// The mode switched to, e.g., `locked` or `normal`.
+    pub mode: String,
+    /// The trigger that locked zellij, if any.
+    pub trigger: Option<String>,
+    pub pane: u32,
+    /// Seconds since the Unix epoch.
+    pub timestamp: f64,
+}
+
+impl SwitchEvent {
+    pub fn to_json(&self) -> String {
+        // A struct of strings and numbers always serializes.
+        serde_json::to_string(self).unwrap()
+    }
+
+    pub fn from_json(json: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(json)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_commands() {
+        for command in [
+            Command::Enable,
+            Command::Disable,
+            Command::Toggle,
+            Command::RequestPermissions,
+            Command::Status,
+            Command::Panes,
+            Command::DumpTrace,
+            Command::Stats,
+            Command::Doctor,
+            Command::Latency,
+            Command::Undo,
+            Command::AddTrigger("git log*".to_string()),
+            Command::RemoveTrigger("fzf".to_string()),
+            Command::EditorEnter,
+            Command::EditorLeave,
+            Command::RequestUnlock(2.5),
+            Command::CmdStart("git log  -p".to_string()),
+            Command::CmdEnd,
+            Command::Embedded,
+        ] {
+            assert_eq!(Command::parse(&command.to_string()), Some(command));
+        }
+        assert_eq!(Command::parse("cmd-start"), None);
+        assert_eq!(Command::parse("add-trigger "), None);
+        assert_eq!(Command::parse(" dump-trace\n"), Some(Command::DumpTrace));
+        assert_eq!(Command::parse("left"), None);
+        assert_eq!(Command::parse("status now"), None);
+        assert_eq!(Command::parse("request-unlock"), None);
+        assert_eq!(Command::parse("request-unlock -1"), None);
+    }
+
+    #[test]
+    fn round_trips_switch_events() {
+        let switch_event = SwitchEvent {
+            event: SwitchEventKind::Lock,
+            mode: "locked".to_string(),
+            trigger: Some("vim".to_string()),
+            pane: 1,
+            timestamp: 1.5,
+        };
+        let json = switch_event.to_json();
+        assert_eq!(
+            json,
+            r#"{"event":"lock","mode":"locked","trigger":"vim","pane":1,"timestamp":1.5}"#
+        );
+        assert_eq!(SwitchEvent::from_json(&json).unwrap(), switch_event);
+    }
+}